@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use hmac::{Hmac, Mac};
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+
+use crate::crypto::{Kdf, Key};
+
+/// Length of the random challenge of the handshake.
+pub const CHALLENGE_SIZE: usize = 32;
+
+/// Users with this role are allowed to do anything, including granting and revoking permissions.
+pub const ADMIN_ROLE: &str = "admin";
+
+/// A user as it is configured in the users file.
+#[derive(Deserialize)]
+struct UserConfig {
+    name: String,
+    secret: String,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+struct User {
+    key: [u8; 32],
+    roles: Vec<String>,
+}
+
+/// Every user that is allowed to connect, with the key derived from its secret.
+///
+/// The secrets themselves are never sent over the wire, instead the client proves that it knows the secret
+/// by answering a random challenge with `HMAC-SHA256(key, challenge)`.
+/// The key is derived from the secret using the [`Kdf`] with the name of the user appended to the salt.
+pub struct Users {
+    users: HashMap<String, User>,
+}
+
+impl Users {
+    /// Reads the users from the JSON file at `USERS_FILE`.
+    ///
+    /// If `ADMIN_SECRET` is set, a user named `ADMIN_USER` with the admin role is added,
+    /// which makes the users file optional, e.g. for containers.
+    pub fn from_env(kdf: &Kdf) -> Result<Self, String> {
+        let path = std::env::var("USERS_FILE").ok();
+        let admin = std::env::var("ADMIN_SECRET").ok().map(|secret| UserConfig {
+            name: std::env::var("ADMIN_USER").unwrap_or(ADMIN_ROLE.to_string()),
+            secret,
+            roles: vec![ADMIN_ROLE.to_string()],
+        });
+        let mut configs = match (path, &admin) {
+            (Some(path), _) => Self::read(&path)?,
+            (None, None) => Self::read("./users.json")?,
+            // Without an explicit path, the default file is only read if it exists
+            (None, Some(_)) => match std::path::Path::new("./users.json").exists() {
+                true => Self::read("./users.json")?,
+                false => Vec::new(),
+            },
+        };
+        configs.extend(admin);
+        Self::new(configs, kdf)
+    }
+
+    /// Derives the key of every configured user, names have to be unique.
+    fn new(configs: Vec<UserConfig>, kdf: &Kdf) -> Result<Self, String> {
+        let mut users = HashMap::new();
+        for config in configs {
+            let mut key = [0u8; 32];
+            kdf.derive(config.secret.as_bytes(), config.name.as_bytes(), &mut key);
+            if users.insert(config.name.clone(), User { key, roles: config.roles }).is_some() {
+                return Err(format!("User \"{}\" is configured more than once", config.name));
+            }
+        }
+        Ok(Self { users })
+    }
+
+    fn read(path: &str) -> Result<Vec<UserConfig>, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read users file {}: {}", path, err))?;
+        serde_json::from_str::<Vec<UserConfig>>(&json)
+            .map_err(|err| format!("Users file {} is invalid: {}", path, err))
+    }
+
+    /// Names of every user.
+    pub fn names(&self) -> Vec<&String> {
+        let mut names = self.users.keys().collect::<Vec<&String>>();
+        names.sort();
+        names
+    }
+
+    /// Creates a new random challenge.
+    pub fn challenge() -> [u8; CHALLENGE_SIZE] {
+        let mut challenge = [0u8; CHALLENGE_SIZE];
+        OsRng.fill_bytes(&mut challenge);
+        challenge
+    }
+
+    /// Returns true if the proof is the HMAC of the challenge keyed with the key of the user.
+    pub fn verify(&self, name: &str, challenge: &[u8], proof: &[u8]) -> bool {
+        let user = match self.users.get(name) {
+            Some(user) => user,
+            None => return false,
+        };
+        match Hmac::<Sha256>::new_from_slice(&user.key) {
+            Ok(mut mac) => {
+                mac.update(challenge);
+                // Compares in constant time
+                mac.verify_slice(proof).is_ok()
+            },
+            Err(_) => false,
+        }
+    }
+
+    /// Key of the session the user authenticated with the challenge, see [`Key::session`].
+    pub fn session_key(&self, name: &str, challenge: &[u8]) -> Option<Key> {
+        self.users.get(name).map(|user| Key::session(&user.key, challenge))
+    }
+
+    /// Roles of the user, which decide what the user is allowed to do.
+    pub fn roles(&self, name: &str) -> Vec<String> {
+        self.users.get(name).map(|user| user.roles.clone()).unwrap_or_default()
+    }
+}
+
+/// What a role is allowed to do with a template and its instances.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Permission {
+    /// Query instances and see the template.
+    Read,
+    /// Set fields of instances.
+    Write,
+    /// Declare the template and create instances of it.
+    Create,
+    /// Delete instances and the template itself.
+    Delete,
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Permission::Read => "READ",
+            Permission::Write => "WRITE",
+            Permission::Create => "CREATE",
+            Permission::Delete => "DELETE",
+        };
+        f.write_str(name)
+    }
+}
+
+/// `GRANT <permission> ON TYPE "template" TO "role"`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Grant {
+    pub permission: Permission,
+    pub template: String,
+    pub role: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::Kdf;
+
+    use super::*;
+
+    fn kdf() -> Kdf {
+        Kdf::new("test".to_string(), 1)
+    }
+
+    fn users() -> Users {
+        let config = |name: &str, secret: &str, roles: &[&str]| UserConfig {
+            name: name.to_string(),
+            secret: secret.to_string(),
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+        };
+        Users::new(vec![config("alice", "wonderland", &["reporting"]), config("bob", "builder", &[])], &kdf()).unwrap()
+    }
+
+    /// What a client sends to prove that it knows the secret of the user.
+    fn proof(name: &str, secret: &str, challenge: &[u8]) -> Vec<u8> {
+        let mut key = [0u8; 32];
+        kdf().derive(secret.as_bytes(), name.as_bytes(), &mut key);
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).unwrap();
+        mac.update(challenge);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    #[test]
+    fn verifies_proofs_of_the_secret() {
+        let users = users();
+        let challenge = Users::challenge();
+        assert!(users.verify("alice", &challenge, &proof("alice", "wonderland", &challenge)));
+        assert!(!users.verify("alice", &challenge, &proof("alice", "looking glass", &challenge)));
+        assert!(!users.verify("alice", &Users::challenge(), &proof("alice", "wonderland", &challenge)));
+        // The name is part of the salt, so users with the same secret still have different keys
+        assert!(!users.verify("bob", &challenge, &proof("alice", "builder", &challenge)));
+        assert!(!users.verify("alice", &challenge, &[]));
+        assert!(!users.verify("carol", &challenge, &proof("carol", "wonderland", &challenge)));
+        assert_eq!(users.roles("alice"), vec!["reporting".to_string()]);
+        assert!(users.roles("carol").is_empty());
+    }
+
+    #[test]
+    fn derives_session_keys_from_the_user_key() {
+        let users = users();
+        let challenge = Users::challenge();
+        let mut key = [0u8; 32];
+        kdf().derive(b"wonderland", b"alice", &mut key);
+        let encrypted = Key::session(&key, &challenge).encrypt(b"QUERY TYPE;".to_vec(), b"header");
+
+        let session = users.session_key("alice", &challenge).unwrap();
+        assert_eq!(session.decrypt(encrypted.clone(), b"header").unwrap(), b"QUERY TYPE;");
+        assert!(users.session_key("bob", &challenge).unwrap().decrypt(encrypted.clone(), b"header").is_err());
+        assert!(users.session_key("alice", &Users::challenge()).unwrap().decrypt(encrypted, b"header").is_err());
+        assert!(users.session_key("carol", &challenge).is_none());
+    }
+
+    #[test]
+    fn reads_users_files() {
+        let path = std::env::temp_dir().join(format!("perdia-users-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, r#"[{"name": "alice", "secret": "wonderland", "roles": ["reporting"]}, {"name": "bob", "secret": "builder"}]"#).unwrap();
+        let configs = Users::read(path).unwrap();
+        assert_eq!(configs.iter().map(|config| config.name.as_str()).collect::<Vec<&str>>(), ["alice", "bob"]);
+        assert!(configs[1].roles.is_empty());
+        assert_eq!(Users::new(configs, &kdf()).unwrap().names(), ["alice", "bob"]);
+
+        std::fs::write(path, r#"[{"name": "alice"}]"#).unwrap();
+        assert!(Users::read(path).err().unwrap_or_default().starts_with("Users file"));
+        std::fs::remove_file(path).unwrap();
+        assert!(Users::read(path).err().unwrap_or_default().starts_with("Failed to read users file"));
+    }
+
+    #[test]
+    fn rejects_duplicate_users() {
+        let config = || UserConfig { name: "alice".to_string(), secret: "wonderland".to_string(), roles: Vec::new() };
+        assert_eq!(Users::new(vec![config(), config()], &kdf()).err(), Some("User \"alice\" is configured more than once".to_string()));
+    }
+}
@@ -1,6 +1,7 @@
-use std::{sync::{Arc, Mutex, mpsc::{self, Receiver, Sender}, atomic::{AtomicBool, Ordering}}, thread::{self, JoinHandle}, time::{Duration, Instant}, path::Path};
+use std::{sync::{Arc, atomic::{AtomicBool, Ordering}}, thread::{self, JoinHandle}, time::Instant, path::Path};
 use lazy_static::lazy_static;
-use crate::{data::{INSTANCES, TEMPLATES, template::Template}, plog, pwarn, perr};
+use serde::de::DeserializeOwned;
+use crate::{data::{INSTANCES, TEMPLATES, GRANTS, lock, template::Template, serialization::{Data, DataType}}, auth::Grant, plog, pwarn, perr};
 
 lazy_static! {
     static ref SAVE_DIR: String = std::env::var("DIR").unwrap_or("./backup/".to_string());
@@ -35,51 +36,120 @@ impl SaveWorker {
         }
     }
 
-    /// Load values from disk into memory
-    fn load() {
-        let instances_res = std::fs::read_to_string(format!("{}/instances.json", SAVE_DIR.to_string()));
-        let templates_res = std::fs::read_to_string(format!("{}/templates.json", SAVE_DIR.to_string()));
-
-        match instances_res {
-            Ok(json_string) => {
-                match serde_json::from_str::<Vec<Template>>(&json_string) {
-                    Ok(instances) => {
-                        let mut mutex = INSTANCES.lock().unwrap();
-                        *mutex = instances;
-                        plog!("Successfully loaded instance backup!")
-                    },
-                    Err(_) => pwarn!("No previous backup file was invalid!"),
-                }
+    /// Reads a backup file, returns `None` if there is no valid one.
+    fn read<T: DeserializeOwned>(file: &str) -> Option<T> {
+        let json_string = match std::fs::read_to_string(format!("{}/{}", *SAVE_DIR, file)) {
+            Ok(json_string) => json_string,
+            Err(_) => {
+                pwarn!("No previous backup file {}!", file);
+                return None;
+            },
+        };
+        match serde_json::from_str::<T>(&json_string) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                pwarn!("Previous backup file {} was invalid: {}", file, err);
+                None
             },
-            Err(_) => pwarn!("No previous backup file for templates!"),
         }
+    }
 
-        match templates_res {
-            Ok(json_string) => {
-                match serde_json::from_str::<Vec<Template>>(&json_string) {
-                    Ok(templates) => {
-                        let mut mutex = TEMPLATES.lock().unwrap();
-                        *mutex = templates;
-                        plog!("Successfully loaded template backup!")
-                    },
-                    Err(_) => pwarn!("No previous backup file was invalid!"),
+    /// Backups from before templates kept their schema only contain strings, integers and floats.
+    fn infer_schema(template: &mut Template) {
+        for (name, value) in template.data.iter() {
+            let data_type = match value {
+                Data::Integer(_) => DataType::INTEGER,
+                Data::Float(_) => DataType::FLOAT,
+                _ => DataType::STRING,
+            };
+            template.schema.insert(name.clone(), data_type);
+        }
+    }
+
+    /// Restores the types of the values of templates as they were read from JSON.
+    fn restore_templates(templates: &mut [Template]) {
+        // Embedded templates are restored using the templates as they were read
+        let read = templates.to_vec();
+        for template in templates.iter_mut() {
+            if template.schema.is_empty() {
+                SaveWorker::infer_schema(template);
+            }
+            let schema = template.schema.clone();
+            if let Err(err) = template.restore_types(&schema, &read) {
+                pwarn!("Failed to restore template {:?}: {}", template.template, err);
+            }
+        }
+    }
+
+    /// Migrates instances as they were read from JSON to the version of their template and restores the types of their values.
+    fn restore_instances(instances: &mut [Template], templates: &[Template]) {
+        for instance in instances.iter_mut() {
+            let template = templates.iter().find(|t| t.template == instance.template);
+            if let Some(template) = template {
+                if instance.version < template.version {
+                    let version = instance.version;
+                    match instance.migrate(template) {
+                        Ok(_) => plog!("Migrated instance {:?} from version {} to {}.", instance.instance, version, template.version),
+                        Err(err) => pwarn!("Failed to migrate instance {:?}: {}", instance.instance, err),
+                    }
                 }
-            },
-            Err(_) => pwarn!("No previous backup file for instances!"),
+                if let Err(err) = instance.restore_types(&template.schema, templates) {
+                    pwarn!("Failed to restore instance {:?}: {}", instance.instance, err);
+                }
+            }
+        }
+    }
+
+    /// Load values from disk into memory
+    ///
+    /// Templates are loaded first, since their schemas are needed to restore the types of the instance values.
+    /// Instances saved at an older version of their template are migrated forward using its history.
+    fn load() {
+        if let Some(mut templates) = SaveWorker::read::<Vec<Template>>("templates.json") {
+            SaveWorker::restore_templates(&mut templates);
+            *lock(&TEMPLATES) = templates;
+            plog!("Successfully loaded template backup!")
+        }
+
+        if let Some(mut instances) = SaveWorker::read::<Vec<Template>>("instances.json") {
+            let templates = lock(&TEMPLATES);
+            SaveWorker::restore_instances(&mut instances, &templates);
+            *lock(&INSTANCES) = instances;
+            plog!("Successfully loaded instance backup!")
+        }
+
+        if let Some(grants) = SaveWorker::read::<Vec<Grant>>("grants.json") {
+            *lock(&GRANTS) = grants;
+            plog!("Successfully loaded grant backup!")
         }
     }
 
     /// Write memory to disk
     fn save() {
-        let instances_mutex = INSTANCES.lock().unwrap();
-        let templates_mutex = TEMPLATES.lock().unwrap();
-        std::fs::write(format!("{}/instances.json", SAVE_DIR.to_string()), serde_json::to_string_pretty(&*instances_mutex).unwrap()).expect("Failed to write backup.");
-        std::fs::write(format!("{}/templates.json", SAVE_DIR.to_string()), serde_json::to_string_pretty(&*templates_mutex).unwrap()).expect("Failed to write backup.");
+        // Serialize under the locks, but write to disk after releasing them
+        let (instances, templates, grants) = {
+            // Same order as the statements lock them in, to avoid deadlocks
+            let templates_mutex = lock(&TEMPLATES);
+            let instances_mutex = lock(&INSTANCES);
+            let grants_mutex = lock(&GRANTS);
+            (
+                serde_json::to_string_pretty(&*instances_mutex),
+                serde_json::to_string_pretty(&*templates_mutex),
+                serde_json::to_string_pretty(&*grants_mutex),
+            )
+        };
+        for (file, json) in [("instances.json", instances), ("templates.json", templates), ("grants.json", grants)] {
+            let result = json.map_err(|err| err.to_string())
+                .and_then(|json| std::fs::write(format!("{}/{}", *SAVE_DIR, file), json).map_err(|err| err.to_string()));
+            if let Err(err) = result {
+                perr!("Failed to write backup {}: {}", file, err);
+            }
+        }
     }
 
     /// Background thread that handles the backups.
     fn background(shutdown: Arc<AtomicBool>) {
-        let interval_time = u64::from_str_radix(std::env::var("SAVE_FREQ").unwrap_or("120".to_string()).as_str(), 10).unwrap_or(120);
+        let interval_time = std::env::var("SAVE_FREQ").unwrap_or("120".to_string()).parse::<u64>().unwrap_or(120);
         SaveWorker::load();
 
         plog!("Started background process, save interval is {}s.", interval_time);
@@ -114,4 +184,76 @@ impl Drop for SaveWorker {
     fn drop(&mut self) {
         self.shutdown();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use crate::data::history::{Change, Revision};
+
+    use super::*;
+
+    /// Writes the values to JSON the way [`SaveWorker::save`] does and reads them back.
+    fn reload(values: &[Template]) -> Vec<Template> {
+        let json = serde_json::to_string_pretty(values).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn restores_types_after_reloading() {
+        let badge = Template::new("BADGE".to_string())
+            .with_bytes("Chip".to_string(), Some(vec![1, 2, 3]))
+            .build();
+        let shift = Template::new("SHIFT".to_string())
+            .with_boolean("Night".to_string(), Some(false))
+            .with_timestamp("Start".to_string(), Some(datetime!(2022-05-02 22:00 UTC)))
+            .with_bytes("Signature".to_string(), None)
+            .with_list("Breaks".to_string(), DataType::TIMESTAMP)
+            .with_template("Badge".to_string(), &badge)
+            .build();
+        let mut instance = Template { instance: Some("Monday".to_string()), ..shift.clone() };
+        instance.data.insert("Night".to_string(), Data::Boolean(true));
+        instance.data.insert("Signature".to_string(), Data::Bytes(vec![0, 255]));
+        instance.data.insert("Breaks".to_string(), Data::List(vec![Data::Timestamp(datetime!(2022-05-03 01:30 +2))]));
+        let templates = vec![badge, shift];
+
+        let mut loaded_templates = reload(&templates);
+        SaveWorker::restore_templates(&mut loaded_templates);
+        assert_eq!(loaded_templates, templates);
+
+        let mut loaded = reload(&[instance.clone()]);
+        assert_eq!(loaded[0].data.get("Signature"), Some(&Data::String("AP8=".to_string())));
+        SaveWorker::restore_instances(&mut loaded, &loaded_templates);
+        assert_eq!(loaded, vec![instance]);
+        assert!(matches!(loaded[0].get("Badge.Chip"), Some(Data::Bytes(_))));
+        assert!(matches!(loaded[0].get("Breaks[0]"), Some(Data::Timestamp(_))));
+    }
+
+    #[test]
+    fn migrates_instances_saved_at_older_versions() {
+        let revision = |version, change| Revision { version, user: "admin".to_string(), path: String::new(), change };
+        let mut template = Template::new("SHIFTED".to_string())
+            .with_integer("Key".to_string(), None)
+            .build();
+        template.version = 3;
+        template.history = vec![
+            revision(2, Change::Rename { field: "Code".to_string(), to: "Key".to_string() }),
+            revision(3, Change::Modify { field: "Key".to_string(), data_type: DataType::INTEGER }),
+        ];
+        let saved = |name: &str, code: &str| {
+            let mut instance = Template { instance: Some(name.to_string()), schema: Default::default(), history: Vec::new(), ..template.clone() };
+            instance.version = 1;
+            instance.data = [("Code".to_string(), Data::String(code.to_string()))].into_iter().collect();
+            instance
+        };
+        let (numeric, text) = (saved("Numeric", "1"), saved("Text", "x"));
+
+        let mut loaded = reload(&[numeric, text.clone()]);
+        SaveWorker::restore_instances(&mut loaded, &[template]);
+        assert_eq!(loaded[0].version, 3);
+        assert_eq!(loaded[0].data, [("Key".to_string(), Data::Integer(1))].into_iter().collect());
+        // The rename applied, but the conversion failed, so none of the revisions is kept
+        assert_eq!(loaded[1], text);
+    }
+}
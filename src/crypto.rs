@@ -1,99 +1,253 @@
-use aes::{cipher::{generic_array::GenericArray, typenum::{UInt, UTerm}, consts::{B1, B0}, KeyInit, BlockEncrypt, BlockDecrypt}, Aes128};
-use crypto::{sha3::Sha3, digest::Digest};
+use std::{collections::HashMap, num::NonZeroU32};
 
-/// An abstraction for the underlying AES128 and Shake128 ciphers
+use aes_gcm::{aead::{Aead, AeadCore, OsRng, Payload}, Aes128Gcm, KeyInit, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+/// Length of the random nonce in front of every message.
+const NONCE_SIZE: usize = 12;
+
+/// Length of the authentication tag at the end of every message.
+const TAG_SIZE: usize = 16;
+
+/// How many bytes longer an encrypted message is than the message itself.
+pub const OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+/// Separates session keys from any other key derived with HKDF.
+const SESSION_INFO: &[u8] = b"perdia-db session";
+
+/// Parameters of PBKDF2-HMAC-SHA256, which derives keys from secrets.
+pub struct Kdf {
+    salt: String,
+    iterations: u32,
+}
+
+impl Kdf {
+    /// Reads the salt from `KDF_SALT` and the number of iterations from `KDF_ITERATIONS`.
+    pub fn from_env() -> Result<Self, String> {
+        let salt = std::env::var("KDF_SALT").unwrap_or("perdia-db".to_string());
+        let iterations = std::env::var("KDF_ITERATIONS").unwrap_or("100000".to_string()).parse::<NonZeroU32>()
+            .map_err(|_| "KDF_ITERATIONS has to be a positive number".to_string())?;
+        Ok(Self::new(salt, iterations.get()))
+    }
+
+    pub fn new(salt: String, iterations: u32) -> Self {
+        Self { salt, iterations }
+    }
+
+    /// Fills the output with the key derived from the secret, the context gets appended to the salt.
+    ///
+    /// This is slow on purpose, so it should only happen once per secret.
+    pub fn derive(&self, secret: &[u8], context: &[u8], output: &mut [u8]) {
+        let salt = [self.salt.as_bytes(), context].concat();
+        pbkdf2::pbkdf2_hmac::<Sha256>(secret, &salt, self.iterations, output);
+    }
+}
+
+/// An abstraction for the underlying AES128-GCM cipher
 /// 
 /// Key operates as follows:
 /// 
-/// The [`Key`] generates a Shake128 hash of the passed raw key([`Vec<u8>`]),
-/// it then takes encrypts or decrypts a certain number of bytes ([`Vec<u8>`]) using AES128 and returns the result.
+/// The [`Key`] derives the cipher key from a secret using PBKDF2-HMAC-SHA256,
+/// it then encrypts or decrypts a message ([`Vec<u8>`]) using AES128-GCM and returns the result.
+///
+/// Every encrypted message starts with the random nonce it was encrypted with,
+/// followed by the ciphertext and the authentication tag.
 pub struct Key {
-    key: GenericArray<u8, UInt<UInt<UInt<UInt<UInt<UTerm, B1>, B0>, B0>, B0>, B0>>,
-    cipher: Aes128,
+    cipher: Aes128Gcm,
 }
 
 impl Key {
-    /// Hashing the raw key using Shake128
-    pub fn new(key: Vec<u8>) -> Self {
-        let mut hasher = Sha3::shake_128();
-        hasher.input(&key);
-        let mut key_array = [0u8; 16];
-        hasher.result(&mut key_array);
-        let key = GenericArray::from(key_array);
-        let cipher = Aes128::new(&key.into());
-        Self { key, cipher }
-    }
-
-    // TODO: Improve performance
-    /// Encrypt some bytes([`Vec<u8>`]) using AES128.
-    pub fn encrypt(&self, mut bytes: Vec<u8>) -> Vec<u8> {
-        let mut output: Vec<u8> = Vec::with_capacity(bytes.len());
-        'encrypt_loop: 
-        loop {
-            if bytes.len() <= 16 {
-                for _ in bytes.len()..16 {
-                    bytes.push(0);
-                }
-                let mut block: [u8; 16] = [0u8; 16];
-                block.copy_from_slice(bytes.as_slice());
-                let mut block = GenericArray::from(block);
-                self.cipher.encrypt_block(&mut block);
-                output.extend(&block);
-                break 'encrypt_loop;
-            } else {
-                let (slice, b) = bytes.split_at(16);
-                let mut block: [u8; 16] = [0u8; 16];
-                block.copy_from_slice(slice);
-                let mut block = GenericArray::from(block);
-                self.cipher.encrypt_block(&mut block);
-                output.extend(&block);
-                bytes = b.to_vec();
-            }
+    /// Derives the cipher key from the secret.
+    pub fn derive(secret: &[u8], kdf: &Kdf) -> Self {
+        let mut key = [0u8; 16];
+        kdf.derive(secret, &[], &mut key);
+        Self { cipher: Aes128Gcm::new(&key.into()) }
+    }
 
-        }
-        output
+    /// Derives the key of a session from the key of its user and the challenge of its handshake using HKDF-SHA256.
+    ///
+    /// Only the user and the server know the key of the user, so nobody else can encrypt frames of the session,
+    /// not even with the secret of the transport key.
+    pub fn session(user_key: &[u8], challenge: &[u8]) -> Self {
+        let mut key = [0u8; 16];
+        // Expanding only fails for outputs far longer than a key
+        let _ = Hkdf::<Sha256>::new(Some(challenge), user_key).expand(SESSION_INFO, &mut key);
+        Self { cipher: Aes128Gcm::new(&key.into()) }
     }
 
-    // TODO: Improve performance
-    /// Decrypt the given bytes([`Vec<u8>`]) using AES128.
-    pub fn decrypt(&self, mut bytes: Vec<u8>) -> Vec<u8> {
-        let mut output: Vec<u8> = Vec::with_capacity(bytes.len());
-        'encrypt_loop: 
-        loop {
-            if bytes.len() == 0 {
-                break 'encrypt_loop;
-            } else {
-                let (slice, b) = bytes.split_at(16);
-                let mut block: [u8; 16] = [0u8; 16];
-                block.copy_from_slice(slice);
-                let mut block = GenericArray::from(block);
-                self.cipher.decrypt_block(&mut block);
-                output.extend(&block);
-                bytes = b.to_vec();
-            }
+    /// Encrypt some bytes([`Vec<u8>`]) using AES128-GCM with a new random nonce.
+    ///
+    /// The associated data isn't part of the output, but the message only decrypts with the same associated data.
+    pub fn encrypt(&self, bytes: Vec<u8>, aad: &[u8]) -> Vec<u8> {
+        let nonce = Aes128Gcm::generate_nonce(&mut OsRng);
+        // Encryption only fails for messages larger than GCM supports, which frames can't hold anyway
+        let ciphertext = self.cipher.encrypt(&nonce, Payload { msg: &bytes, aad }).unwrap_or_default();
+        let mut output = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        output.extend_from_slice(&nonce);
+        output.extend(ciphertext);
+        output
+    }
 
+    /// Decrypt the given bytes([`Vec<u8>`]) using AES128-GCM.
+    ///
+    /// Fails if the authentication tag doesn't match, which means the message or the associated data was tampered with
+    /// or the message was encrypted using another key.
+    pub fn decrypt(&self, bytes: Vec<u8>, aad: &[u8]) -> Result<Vec<u8>, DecryptionError> {
+        if bytes.len() < NONCE_SIZE {
+            return Err(DecryptionError);
         }
-        output
+        let (nonce, ciphertext) = bytes.split_at(NONCE_SIZE);
+        self.cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad }).map_err(|_| DecryptionError)
     }
 
 }
 
-impl From<Vec<u8>> for Key {
-    fn from(key: Vec<u8>) -> Self {
-        Self::new(key)
+/// The message couldn't be decrypted, because it is malformed or failed to authenticate.
+#[derive(Debug)]
+pub struct DecryptionError;
+
+impl std::fmt::Display for DecryptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Failed to decrypt message")
     }
 }
 
-impl From<String> for Key {
-    fn from(key: String) -> Self {
-        let key = key.into_bytes();
-        Self::new(key)
+impl std::error::Error for DecryptionError {}
+
+/// Every active [`Key`] by its id.
+///
+/// Clients state the id of the key they used in every frame,
+/// so a new key can be added and the old one removed later without any downtime.
+pub struct Keyring {
+    keys: HashMap<u8, Key>,
+}
+
+impl Keyring {
+    /// Derives every key configured in the environment.
+    ///
+    /// `AES_KEY` is the key with id 0, further keys are read from `AES_KEYS` as a comma separated list of `id:secret` pairs.
+    pub fn from_env(kdf: &Kdf) -> Result<Self, String> {
+        Self::parse(std::env::var("AES_KEY").ok(), &std::env::var("AES_KEYS").unwrap_or_default(), kdf)
+    }
+
+    /// Derives the keys of the values of `AES_KEY` and `AES_KEYS`.
+    pub fn parse(aes_key: Option<String>, aes_keys: &str, kdf: &Kdf) -> Result<Self, String> {
+        let mut secrets = Vec::new();
+        if let Some(secret) = aes_key {
+            secrets.push((0, secret));
+        }
+        for pair in aes_keys.split(',').filter(|pair| !pair.is_empty()) {
+            let (id, secret) = pair.split_once(':')
+                .ok_or_else(|| format!("Key \"{}\" in AES_KEYS is missing an id", pair))?;
+            let id = id.trim().parse::<u8>()
+                .map_err(|_| format!("Key id \"{}\" in AES_KEYS has to be in the range of 0 to 255", id))?;
+            secrets.push((id, secret.to_string()));
+        }
+        if secrets.is_empty() {
+            return Err("Neither AES_KEY nor AES_KEYS is set".to_string());
+        }
+
+        let mut keys = HashMap::new();
+        for (id, secret) in secrets {
+            if keys.insert(id, Key::derive(secret.as_bytes(), kdf)).is_some() {
+                return Err(format!("Key id {} is used more than once", id));
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn get(&self, id: u8) -> Option<&Key> {
+        self.keys.get(&id)
+    }
+
+    /// Ids of every active key.
+    pub fn ids(&self) -> Vec<u8> {
+        let mut ids = self.keys.keys().copied().collect::<Vec<u8>>();
+        ids.sort();
+        ids
     }
 }
 
-/// Make new [`Key`] from inner key.
-impl Into<GenericArray<u8, UInt<UInt<UInt<UInt<UInt<UTerm, B1>, B0>, B0>, B0>, B0>>> for Key {
-    fn into(self) -> GenericArray<u8, UInt<UInt<UInt<UInt<UInt<UTerm, B1>, B0>, B0>, B0>, B0>> {
-        self.key
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(secret: &str) -> Key {
+        Key::derive(secret.as_bytes(), &Kdf::new("test".to_string(), 1))
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = key("secret");
+        let encrypted = key.encrypt(b"QUERY TYPE;".to_vec(), b"header");
+        assert_eq!(encrypted.len(), "QUERY TYPE;".len() + OVERHEAD);
+        assert_eq!(key.decrypt(encrypted, b"header").unwrap(), b"QUERY TYPE;");
+    }
+
+    #[test]
+    fn rejects_tampered_ciphertext() {
+        let key = key("secret");
+        let encrypted = key.encrypt(b"QUERY TYPE;".to_vec(), b"header");
+        for index in [0, NONCE_SIZE, encrypted.len() - 1] {
+            let mut tampered = encrypted.clone();
+            tampered[index] ^= 1;
+            assert!(key.decrypt(tampered, b"header").is_err());
+        }
+        assert!(key.decrypt(encrypted[..NONCE_SIZE + 4].to_vec(), b"header").is_err());
+    }
+
+    #[test]
+    fn session_keys_differ_per_challenge() {
+        let encrypted = Key::session(b"user key", b"first challenge").encrypt(b"QUERY TYPE;".to_vec(), b"header");
+        assert!(Key::session(b"user key", b"first challenge").decrypt(encrypted.clone(), b"header").is_ok());
+        assert!(Key::session(b"user key", b"other challenge").decrypt(encrypted.clone(), b"header").is_err());
+        assert!(Key::session(b"other key", b"first challenge").decrypt(encrypted, b"header").is_err());
+    }
+
+    #[test]
+    fn reads_keys_by_id() {
+        let kdf = Kdf::new("test".to_string(), 1);
+        let keyring = Keyring::parse(Some("first".to_string()), "7:second, 255:third", &kdf).unwrap();
+        assert_eq!(keyring.ids(), vec![0, 7, 255]);
+        let encrypted = key("second").encrypt(b"QUERY TYPE;".to_vec(), b"header");
+        assert!(keyring.get(7).unwrap().decrypt(encrypted, b"header").is_ok());
+        assert!(keyring.get(1).is_none());
+
+        // Secrets can contain the separator of the id
+        assert_eq!(Keyring::parse(None, "3:a:b", &kdf).unwrap().ids(), vec![3]);
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        let kdf = Kdf::new("test".to_string(), 1);
+        let error = |aes_key: Option<&str>, aes_keys: &str| {
+            Keyring::parse(aes_key.map(str::to_string), aes_keys, &kdf).err().unwrap_or_default()
+        };
+        assert_eq!(error(Some("first"), "0:second"), "Key id 0 is used more than once");
+        assert_eq!(error(None, "1:first,1:second"), "Key id 1 is used more than once");
+        assert_eq!(error(None, "secret"), "Key \"secret\" in AES_KEYS is missing an id");
+        assert_eq!(error(None, "0x1:secret"), "Key id \"0x1\" in AES_KEYS has to be in the range of 0 to 255");
+        assert_eq!(error(None, "256:secret"), "Key id \"256\" in AES_KEYS has to be in the range of 0 to 255");
+        assert_eq!(error(None, ""), "Neither AES_KEY nor AES_KEYS is set");
+    }
+
+    #[test]
+    fn rejects_zero_iterations() {
+        // No other test reads KDF_ITERATIONS
+        for iterations in ["0", "-1", "many"] {
+            std::env::set_var("KDF_ITERATIONS", iterations);
+            assert!(Kdf::from_env().is_err(), "{}", iterations);
+        }
+        std::env::set_var("KDF_ITERATIONS", "1");
+        assert_eq!(Kdf::from_env().map(|kdf| kdf.iterations), Ok(1));
+        std::env::remove_var("KDF_ITERATIONS");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rejects_other_associated_data_and_keys() {
+        let encrypted = key("secret").encrypt(b"QUERY TYPE;".to_vec(), b"header");
+        assert!(key("secret").decrypt(encrypted.clone(), b"Header").is_err());
+        assert!(key("other").decrypt(encrypted, b"header").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use regex::Regex;
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+
+use super::serialization::{Data, DataType};
+
+/// A rule the value of a field has to follow, declared after the type of the field.
+///
+/// Constraints on lists apply to every item, except `MAXLEN` which limits the number of items.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Constraint {
+    /// The field can't be null.
+    Required,
+    /// No two instances of the template may have the same value, except for null.
+    Unique,
+    Min(f64),
+    Max(f64),
+    /// The string has to match the regex.
+    Matches(Pattern),
+    /// Maximum number of characters, bytes or items.
+    #[serde(rename = "MAXLEN")]
+    MaxLen(usize),
+}
+
+impl Constraint {
+    /// Returns true if the constraint can be declared on a field of the type.
+    pub fn fits(&self, data_type: &DataType) -> bool {
+        match (self, data_type) {
+            (Constraint::MaxLen(_), DataType::STRING | DataType::BYTES | DataType::LIST(_)) => true,
+            (_, DataType::LIST(element)) => self.fits(element),
+            (Constraint::Required, _) => true,
+            (Constraint::Unique, DataType::TEMPLATE(_)) => false,
+            (Constraint::Unique, _) => true,
+            (Constraint::Min(_) | Constraint::Max(_), DataType::INTEGER | DataType::FLOAT) => true,
+            (Constraint::Matches(_), DataType::STRING) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the value follows the constraint.
+    ///
+    /// `REQUIRED` and `UNIQUE` depend on more than the value, so they are checked by the executor.
+    pub fn allows(&self, value: &Data) -> bool {
+        match (self, value) {
+            (Constraint::MaxLen(max), Data::List(items)) => items.len() <= *max,
+            (_, Data::List(items)) => items.iter().all(|item| self.allows(item)),
+            (Constraint::Min(min), Data::Integer(int)) => *int as f64 >= *min,
+            (Constraint::Min(min), Data::Float(float)) => float >= min,
+            (Constraint::Max(max), Data::Integer(int)) => *int as f64 <= *max,
+            (Constraint::Max(max), Data::Float(float)) => float <= max,
+            (Constraint::Matches(pattern), Data::String(string)) => pattern.0.is_match(string),
+            (Constraint::MaxLen(max), Data::String(string)) => string.chars().count() <= *max,
+            (Constraint::MaxLen(max), Data::Bytes(bytes)) => bytes.len() <= *max,
+            _ => true,
+        }
+    }
+}
+
+/// The regex of a `MATCHES` constraint, compiled once when the template is declared or read from a backup.
+///
+/// It is stored and compared as the pattern it was compiled from.
+#[derive(Clone, Debug)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Pattern, regex::Error> {
+        Regex::new(pattern).map(Pattern)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Pattern, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Pattern::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+impl std::fmt::Display for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constraint::Required => f.write_str("REQUIRED"),
+            Constraint::Unique => f.write_str("UNIQUE"),
+            Constraint::Min(min) => write!(f, "MIN {}", min),
+            Constraint::Max(max) => write!(f, "MAX {}", max),
+            Constraint::Matches(pattern) => write!(f, "MATCHES \"{}\"", pattern.as_str()),
+            Constraint::MaxLen(max) => write!(f, "MAXLEN {}", max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_patterns_as_their_source() {
+        let constraint = Constraint::Matches(Pattern::new("^[A-Z][a-z]+$").unwrap());
+        let json = serde_json::to_string(&constraint).unwrap();
+        assert_eq!(json, r#"{"MATCHES":"^[A-Z][a-z]+$"}"#);
+        let read = serde_json::from_str::<Constraint>(&json).unwrap();
+        assert_eq!(read, constraint);
+        assert!(read.allows(&Data::String("Monday".to_string())));
+        assert!(!read.allows(&Data::String("monday".to_string())));
+        assert!(serde_json::from_str::<Constraint>(r#"{"MATCHES":"["}"#).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use linked_hash_map::LinkedHashMap;
+use serde::{Serialize, Deserialize};
+
+use super::{serialization::{Data, DataType}, template::Template};
+
+/// A change to the fields of a template, as it is applied to every value of the template.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Change {
+    /// Adds the field with the value.
+    Add {
+        field: String,
+        data_type: DataType,
+        value: Data,
+    },
+    Drop {
+        field: String,
+    },
+    Rename {
+        field: String,
+        to: String,
+    },
+    /// Converts the value of the field to the type, see [`Data::convert`].
+    Modify {
+        field: String,
+        data_type: DataType,
+    },
+}
+
+impl Change {
+    /// Applies the change to the fields of a single value, fails with a description if a value can't be converted.
+    pub fn apply(&self, fields: &mut LinkedHashMap<String, Data>) -> Result<(), String> {
+        match self {
+            Change::Add { field, value, .. } => {
+                fields.insert(field.clone(), value.clone());
+            },
+            Change::Drop { field } => {
+                fields.remove(field);
+            },
+            Change::Rename { field, to } => rename(fields, field, to),
+            Change::Modify { field, data_type } => {
+                if let Some(value) = fields.get_mut(field) {
+                    *value = value.clone().convert(data_type).ok_or_else(|| format!(
+                        "Field \"{}\" holds {}, which can't be converted to {}.",
+                        field, serde_json::to_string(value).unwrap_or_default(), data_type,
+                    ))?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// An entry in the history of a template.
+///
+/// Values of the template are at the version of the last revision that was applied to them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Revision {
+    pub version: u32,
+    /// The user that altered the template.
+    pub user: String,
+    /// Path of the embedded value the change applies to, empty for the fields of the template itself.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
+    pub change: Change,
+}
+
+impl Revision {
+    /// Applies the change to the fields of the value or to the value embedded at the path.
+    ///
+    /// Embedded values that are null or missing are left as they are.
+    pub fn apply(&self, value: &mut Template) -> Result<(), String> {
+        if self.path.is_empty() {
+            return self.change.apply(&mut value.data);
+        }
+        match value.get_mut(&self.path) {
+            Some(Data::Object(fields)) => self.change.apply(fields),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Renames the key of the map without changing its position.
+pub fn rename<V>(map: &mut LinkedHashMap<String, V>, from: &str, to: &str) {
+    *map = std::mem::take(map).into_iter()
+        .map(|(key, value)| match key == from {
+            true => (to.to_string(), value),
+            false => (key, value),
+        })
+        .collect();
+}
@@ -1,13 +1,43 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
 use lazy_static::lazy_static;
 
-use super::data::template::Template;
+use super::{data::template::Template, auth::Grant};
 
 pub mod template;
 pub mod serialization;
+pub mod constraint;
+pub mod history;
 
 lazy_static! {
     pub static ref TEMPLATES:  Arc<Mutex<Vec<Template>>> = Arc::new(Mutex::new(Vec::new()));
     pub static ref INSTANCES:  Arc<Mutex<Vec<Template>>> = Arc::new(Mutex::new(Vec::new()));
-}
\ No newline at end of file
+    pub static ref GRANTS:  Arc<Mutex<Vec<Grant>>> = Arc::new(Mutex::new(Vec::new()));
+}
+
+/// Locks the given mutex and recovers it if it was poisoned.
+///
+/// Statements only write their changes back once they succeeded,
+/// so the values behind a poisoned mutex are still consistent.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovers_poisoned_locks() {
+        let mutex = Arc::new(Mutex::new(vec![1]));
+        let poisoner = Arc::clone(&mutex);
+        let result = std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poisons the mutex");
+        }).join();
+        assert!(result.is_err());
+        assert!(mutex.is_poisoned());
+
+        lock(&mutex).push(2);
+        assert_eq!(*lock(&mutex), vec![1, 2]);
+    }
+}
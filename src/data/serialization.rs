@@ -1,115 +1,286 @@
-use serde::{Deserialize, de::Visitor, Serialize};
+use std::cmp::Ordering;
 
-pub union DataUnion {
-    pub string: &'static str,
-    pub integer: i64,
-    pub float: f64,
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use linked_hash_map::LinkedHashMap;
+use serde::{Deserialize, de::{MapAccess, SeqAccess, Visitor}, Serialize};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use super::template::Template;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum DataType {
+    STRING,
+    INTEGER,
+    FLOAT,
+    BOOLEAN,
+    TIMESTAMP,
+    BYTES,
+    /// An embedded value of the template with the given name.
+    TEMPLATE(String),
+    /// A list of values of the given type.
+    LIST(Box<DataType>),
 }
 
-impl From<Option<&'static str>> for DataUnion {
-    fn from(string: Option<&'static str>) -> Self {
-        DataUnion { string: string.unwrap_or_default() }
+impl std::fmt::Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataType::STRING => f.write_str("STRING"),
+            DataType::INTEGER => f.write_str("INTEGER"),
+            DataType::FLOAT => f.write_str("FLOAT"),
+            DataType::BOOLEAN => f.write_str("BOOLEAN"),
+            DataType::TIMESTAMP => f.write_str("TIMESTAMP"),
+            DataType::BYTES => f.write_str("BYTES"),
+            DataType::TEMPLATE(name) => write!(f, "\"{}\"", name),
+            DataType::LIST(element) => write!(f, "LIST OF {}", element),
+        }
     }
 }
 
-impl From<Option<String>> for DataUnion {
-    fn from(string: Option<String>) -> Self {
-        DataUnion { string: Box::leak(string.unwrap_or_default().into_boxed_str()) }
-    }
+/// A single value of a field.
+///
+/// Values are serialized as plain JSON values, without any information about their type.
+/// Timestamps are written as RFC 3339 strings and bytes as base64 strings,
+/// so they can only be told apart from strings using the schema of their template, see [`Data::restore`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Timestamp(OffsetDateTime),
+    Bytes(Vec<u8>),
+    /// The fields of an embedded template, written as a nested JSON object.
+    Object(LinkedHashMap<String, Data>),
+    /// Written as a JSON array.
+    List(Vec<Data>),
+    /// A field that isn't set, written as `null`.
+    Null,
 }
 
-impl From<Option<i64>> for DataUnion {
-    fn from(int: Option<i64>) -> Self {
-        DataUnion { integer: int.unwrap_or_default() }
+impl Data {
+    /// Name of the kind of value, used in error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Data::String(_) => "STRING",
+            Data::Integer(_) => "INTEGER",
+            Data::Float(_) => "FLOAT",
+            Data::Boolean(_) => "BOOLEAN",
+            Data::Timestamp(_) => "TIMESTAMP",
+            Data::Bytes(_) => "BYTES",
+            Data::Object(_) => "OBJECT",
+            Data::List(_) => "LIST",
+            Data::Null => "NULL",
+        }
     }
-}
 
-impl From<Option<f64>> for DataUnion {
-    fn from(float: Option<f64>) -> Self {
-        DataUnion { float: float.unwrap_or_default() }
+    /// Returns the value if it fits into a field of the given type.
+    ///
+    /// Integers are converted for float fields, since that is always safe. Every field can be null.
+    pub fn coerce(self, data_type: &DataType) -> Option<Data> {
+        match (self, data_type) {
+            (Data::Null, _) => Some(Data::Null),
+            (Data::Integer(int), DataType::FLOAT) => Some(Data::Float(int as f64)),
+            (data @ Data::String(_), DataType::STRING)
+            | (data @ Data::Integer(_), DataType::INTEGER)
+            | (data @ Data::Float(_), DataType::FLOAT)
+            | (data @ Data::Boolean(_), DataType::BOOLEAN)
+            | (data @ Data::Timestamp(_), DataType::TIMESTAMP)
+            | (data @ Data::Bytes(_), DataType::BYTES) => Some(data),
+            _ => None,
+        }
     }
-}
 
+    /// Orders two values of the same type, integers and floats are compared by their numeric value.
+    ///
+    /// Returns `None` for null, objects, lists, values of different types and `NaN`.
+    pub fn compare(&self, other: &Data) -> Option<Ordering> {
+        match (self, other) {
+            (Data::Integer(a), Data::Integer(b)) => Some(a.cmp(b)),
+            (Data::Integer(a), Data::Float(b)) => (*a as f64).partial_cmp(b),
+            (Data::Float(a), Data::Integer(b)) => a.partial_cmp(&(*b as f64)),
+            (Data::Float(a), Data::Float(b)) => a.partial_cmp(b),
+            (Data::String(a), Data::String(b)) => Some(a.cmp(b)),
+            (Data::Boolean(a), Data::Boolean(b)) => Some(a.cmp(b)),
+            (Data::Timestamp(a), Data::Timestamp(b)) => Some(a.cmp(b)),
+            (Data::Bytes(a), Data::Bytes(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
-pub enum DataType {
-    STRING,
-    INTEGER,
-    FLOAT,
-}
+    /// Orders any two values, unlike [`Data::compare`] this is a total order that can be used for sorting.
+    ///
+    /// Values of different kinds are ordered by a fixed rank of their kind, with numbers first and null last.
+    /// Integers and floats are compared by their exact numeric value, floats by [`f64::total_cmp`].
+    pub fn total_cmp(&self, other: &Data) -> Ordering {
+        match (self, other) {
+            (Data::Integer(a), Data::Integer(b)) => a.cmp(b),
+            (Data::Integer(a), Data::Float(b)) => compare_exactly(*a, *b),
+            (Data::Float(a), Data::Integer(b)) => compare_exactly(*b, *a).reverse(),
+            (Data::Float(a), Data::Float(b)) => a.total_cmp(b),
+            (Data::Object(a), Data::Object(b)) => a.iter().zip(b.iter())
+                .map(|((a_field, a_value), (b_field, b_value))| a_field.cmp(b_field).then_with(|| a_value.total_cmp(b_value)))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (Data::List(a), Data::List(b)) => a.iter().zip(b.iter())
+                .map(|(a, b)| a.total_cmp(b))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (a, b) => a.compare(b).unwrap_or_else(|| a.rank().cmp(&b.rank())),
+        }
+    }
 
-/// A wrapper for the [`DataUnion`] union to make it typesafe.
-pub struct Data {
-    pub data_type: DataType,
-    pub data: DataUnion,
-}
+    /// Position of the kind of the value in [`Data::total_cmp`], integers and floats share one.
+    fn rank(&self) -> u8 {
+        match self {
+            Data::Integer(_) | Data::Float(_) => 0,
+            Data::String(_) => 1,
+            Data::Boolean(_) => 2,
+            Data::Timestamp(_) => 3,
+            Data::Bytes(_) => 4,
+            Data::Object(_) => 5,
+            Data::List(_) => 6,
+            Data::Null => 7,
+        }
+    }
 
-impl std::fmt::Debug for Data {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Data").field("value", {
-            match self.data_type {
-                DataType::STRING => unsafe { &self.data.string },
-                DataType::INTEGER => unsafe { &self.data.integer },
-                DataType::FLOAT => unsafe { &self.data.float },
-            }
-        }).finish()
+    /// Converts the value of a field whose type was changed by `ALTER TYPE ... MODIFY`.
+    ///
+    /// On top of [`Data::coerce`], values are written as strings the way they are in responses and strings are parsed,
+    /// floats without a fractional part become integers and single values become a list with one item.
+    pub fn convert(self, data_type: &DataType) -> Option<Data> {
+        match (self, data_type) {
+            (Data::List(items), DataType::LIST(element)) => items.into_iter()
+                .map(|item| item.convert(element))
+                .collect::<Option<Vec<Data>>>()
+                .map(Data::List),
+            (data @ (Data::String(_) | Data::Integer(_) | Data::Float(_) | Data::Boolean(_) | Data::Timestamp(_) | Data::Bytes(_)), DataType::LIST(element)) => {
+                data.convert(element).map(|item| Data::List(vec![item]))
+            },
+            (Data::Float(float), DataType::INTEGER) if float.fract() == 0.0 && float >= i64::MIN as f64 && float < i64::MAX as f64 => {
+                Some(Data::Integer(float as i64))
+            },
+            (Data::Integer(int), DataType::STRING) => Some(Data::String(int.to_string())),
+            (Data::Float(float), DataType::STRING) => Some(Data::String(float.to_string())),
+            (Data::Boolean(boolean), DataType::STRING) => Some(Data::String(if boolean { "TRUE" } else { "FALSE" }.to_string())),
+            (Data::Timestamp(timestamp), DataType::STRING) => timestamp.format(&Rfc3339).ok().map(Data::String),
+            (Data::Bytes(bytes), DataType::STRING) => Some(Data::String(BASE64.encode(bytes))),
+            (Data::String(string), DataType::INTEGER) => string.trim().parse().ok().map(Data::Integer),
+            // `NaN`, `inf` and numbers too large for a float parse, but can't be stored as JSON or compared
+            (Data::String(string), DataType::FLOAT) => string.trim().parse::<f64>().ok()
+                .filter(|float| float.is_finite())
+                .map(Data::Float),
+            (Data::String(string), DataType::BOOLEAN) => match string.trim() {
+                "TRUE" => Some(Data::Boolean(true)),
+                "FALSE" => Some(Data::Boolean(false)),
+                _ => None,
+            },
+            (Data::String(string), DataType::TIMESTAMP) => parse_timestamp(&string).ok().map(Data::Timestamp),
+            (Data::String(string), DataType::BYTES) => parse_bytes(&string).ok().map(Data::Bytes),
+            (data, data_type) => data.coerce(data_type),
+        }
     }
-}
 
-impl PartialEq for Data {
-    fn eq(&self, other: &Self) -> bool {
-        self.data_type == other.data_type && match self.data_type {
-            DataType::STRING => unsafe {self.data.string == other.data.string},
-            DataType::INTEGER => unsafe {self.data.integer == other.data.integer},
-            DataType::FLOAT => unsafe {self.data.float == other.data.float},
+    /// Converts a value read from JSON back to the type of its field.
+    ///
+    /// The templates are needed to restore the fields of embedded templates.
+    pub fn restore(self, data_type: &DataType, templates: &[Template]) -> Result<Data, String> {
+        match (self, data_type) {
+            (Data::Object(mut fields), DataType::TEMPLATE(name)) => {
+                let template = templates.iter()
+                    .find(|t| t.template.as_ref() == Some(name))
+                    .ok_or_else(|| format!("Embedded template \"{}\" doesn't exist", name))?;
+                restore_fields(&mut fields, &template.schema, templates)?;
+                Ok(Data::Object(fields))
+            },
+            (Data::List(items), DataType::LIST(element)) => items.into_iter()
+                .map(|item| item.restore(element, templates))
+                .collect::<Result<Vec<Data>, String>>()
+                .map(Data::List),
+            (Data::String(string), DataType::TIMESTAMP) => parse_timestamp(&string)
+                .map(Data::Timestamp)
+                .map_err(|err| format!("Invalid timestamp \"{}\": {}", string, err)),
+            (Data::String(string), DataType::BYTES) => parse_bytes(&string)
+                .map(Data::Bytes)
+                .map_err(|err| format!("Invalid base64 \"{}\": {}", string, err)),
+            (data, _) => Ok(data),
         }
     }
 }
 
-impl Clone for Data {
-    fn clone(&self) -> Self {
-        let data = match self.data_type {
-            DataType::STRING => unsafe { DataUnion { string: self.data.string } },
-            DataType::INTEGER => unsafe { DataUnion { integer: self.data.integer } },
-            DataType::FLOAT => unsafe { DataUnion { float: self.data.float } },
-        };
-        Self { data_type: self.data_type.clone(), data }
+/// Compares an integer to a float without rounding the integer first, like [`f64::total_cmp`] for `NaN` and `-0.0`.
+fn compare_exactly(int: i64, float: f64) -> Ordering {
+    match (int as f64).total_cmp(&float) {
+        // Rounding the integer can make different values equal, but never swaps them,
+        // and floats equal to a rounded integer are whole numbers that fit into an i128
+        Ordering::Equal => (int as i128).cmp(&(float as i128)),
+        ordering => ordering,
     }
 }
 
-impl From<&'static str> for Data {
-    fn from(string: &'static str) -> Self {
-        Self {
-            data_type: DataType::STRING,
-            data: DataUnion::from(Some(string))
+/// Restores the types of every field that is part of the schema, see [`Data::restore`].
+pub fn restore_fields(
+    fields: &mut LinkedHashMap<String, Data>,
+    schema: &LinkedHashMap<String, DataType>,
+    templates: &[Template],
+) -> Result<(), String> {
+    for (name, value) in fields.iter_mut() {
+        if let Some(data_type) = schema.get(name) {
+            *value = value.clone().restore(data_type, templates)?;
         }
     }
+    Ok(())
+}
+
+/// Parses a timestamp with timezone in the format of RFC 3339, e.g. `2022-05-02T08:00:00+02:00`.
+pub fn parse_timestamp(string: &str) -> Result<OffsetDateTime, time::error::Parse> {
+    OffsetDateTime::parse(string, &Rfc3339)
+}
+
+/// Decodes standard base64 with padding.
+pub fn parse_bytes(string: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    BASE64.decode(string)
+}
+
+impl From<&str> for Data {
+    fn from(string: &str) -> Self {
+        Data::String(string.to_string())
+    }
 }
 
 impl From<String> for Data {
     fn from(string: String) -> Self {
-        Self {
-            data_type: DataType::STRING,
-            data: DataUnion::from(Some(string))
-        }
+        Data::String(string)
     }
 }
 
 impl From<i64> for Data {
     fn from(int: i64) -> Self {
-        Self {
-            data_type: DataType::INTEGER,
-            data: DataUnion::from(Some(int))
-        }
+        Data::Integer(int)
     }
 }
 
 impl From<f64> for Data {
     fn from(float: f64) -> Self {
-        Self {
-            data_type: DataType::FLOAT,
-            data: DataUnion::from(Some(float))
-        }
+        Data::Float(float)
+    }
+}
+
+impl From<bool> for Data {
+    fn from(boolean: bool) -> Self {
+        Data::Boolean(boolean)
+    }
+}
+
+impl From<OffsetDateTime> for Data {
+    fn from(timestamp: OffsetDateTime) -> Self {
+        Data::Timestamp(timestamp)
+    }
+}
+
+impl From<Vec<u8>> for Data {
+    fn from(bytes: Vec<u8>) -> Self {
+        Data::Bytes(bytes)
     }
 }
 
@@ -120,10 +291,19 @@ impl Serialize for Data {
     where
         S: serde::Serializer 
     {
-        match self.data_type {
-            DataType::STRING => unsafe { serializer.serialize_str(self.data.string) },
-            DataType::INTEGER => unsafe { serializer.serialize_i64(self.data.integer) },
-            DataType::FLOAT => unsafe { serializer.serialize_f64(self.data.float) },
+        match self {
+            Data::String(string) => serializer.serialize_str(string),
+            Data::Integer(int) => serializer.serialize_i64(*int),
+            Data::Float(float) => serializer.serialize_f64(*float),
+            Data::Boolean(boolean) => serializer.serialize_bool(*boolean),
+            Data::Timestamp(timestamp) => {
+                let string = timestamp.format(&Rfc3339).map_err(serde::ser::Error::custom)?;
+                serializer.serialize_str(&string)
+            },
+            Data::Bytes(bytes) => serializer.serialize_str(&BASE64.encode(bytes)),
+            Data::Object(fields) => serializer.collect_map(fields),
+            Data::List(items) => serializer.collect_seq(items),
+            Data::Null => serializer.serialize_unit(),
         }
     }
 }
@@ -133,146 +313,218 @@ impl<'de> Deserialize<'de> for Data {
     where
         D: serde::Deserializer<'de> 
     {
-        deserializer.deserialize_string(DATAVisitor)   
+        // The JSON value itself decides the type
+        deserializer.deserialize_any(DATAVisitor)
     }
 }
 
+/// Smaller integers and floats are forwarded to the 64 bit variants by serde.
 impl<'de> Visitor<'de> for DATAVisitor {
     type Value = Data;
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("an integer, float or string")
+        formatter.write_str("an integer, float, boolean, string, object, array or null")
     }
 
-    fn visit_u8<E>(self, v: u8) -> Result<Self::Value, E>
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
     where
             E: serde::de::Error, 
     {
-        let v = v as i64;
-        Ok(Data {
-            data_type: DataType::INTEGER,
-            data: DataUnion { integer: v },
-        })
+        Ok(Data::Null)
     }
 
-    fn visit_i8<E>(self, v: i8) -> Result<Self::Value, E>
+    fn visit_none<E>(self) -> Result<Self::Value, E>
     where
             E: serde::de::Error, 
     {
-        let v = v as i64;
-        Ok(Data {
-            data_type: DataType::INTEGER,
-            data: DataUnion { integer: v },
-        })
+        Ok(Data::Null)
     }
 
-    fn visit_u16<E>(self, v: u16) -> Result<Self::Value, E>
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E>
     where
             E: serde::de::Error, 
     {
-        let v = v as i64;
-        Ok(Data {
-            data_type: DataType::INTEGER,
-            data: DataUnion { integer: v },
-        })
+        Ok(Data::Boolean(v))
     }
 
-    fn visit_i16<E>(self, v: i16) -> Result<Self::Value, E>
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
     where
             E: serde::de::Error, 
     {
-        let v = v as i64;
-        Ok(Data {
-            data_type: DataType::INTEGER,
-            data: DataUnion { integer: v },
-        })
+        Ok(Data::Integer(v))
     }
 
-    fn visit_u32<E>(self, v: u32) -> Result<Self::Value, E>
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
     where
             E: serde::de::Error, 
     {
-        let v = v as i64;
-        Ok(Data {
-            data_type: DataType::INTEGER,
-            data: DataUnion { integer: v },
-        })
+        i64::try_from(v)
+            .map(Data::Integer)
+            .map_err(|_| E::invalid_value(serde::de::Unexpected::Unsigned(v), &self))
     }
 
-    fn visit_i32<E>(self, v: i32) -> Result<Self::Value, E>
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
     where
             E: serde::de::Error, 
     {
-        let v = v as i64;
-        Ok(Data {
-            data_type: DataType::INTEGER,
-            data: DataUnion { integer: v },
-        })
+        Ok(Data::Float(v))
     }
 
-    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
     where
             E: serde::de::Error, 
     {
-        let v = v as i64;
-        Ok(Data {
-            data_type: DataType::INTEGER,
-            data: DataUnion { integer: v },
-        })
+        Ok(Data::String(v.to_string()))
     }
 
-    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
     where
             E: serde::de::Error, 
     {
-        let v = v as i64;
-        Ok(Data {
-            data_type: DataType::INTEGER,
-            data: DataUnion { integer: v },
-        })
+        Ok(Data::String(v))
     }
 
-    fn visit_f32<E>(self, v: f32) -> Result<Self::Value, E>
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
     where
-            E: serde::de::Error, 
+            A: MapAccess<'de>,
     {
-        let v = v as f64;
-        Ok(Data {
-            data_type: DataType::FLOAT,
-            data: DataUnion { float: v },
-        })
+        let mut fields = LinkedHashMap::new();
+        while let Some((name, value)) = map.next_entry::<String, Data>()? {
+            fields.insert(name, value);
+        }
+        Ok(Data::Object(fields))
     }
 
-    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
     where
-            E: serde::de::Error, 
+            A: SeqAccess<'de>,
     {
-        let v = v as f64;
-        Ok(Data {
-            data_type: DataType::FLOAT,
-            data: DataUnion { float: v },
-        })
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element::<Data>()? {
+            items.push(item);
+        }
+        Ok(Data::List(items))
     }
+}
 
-    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
-    where
-            E: serde::de::Error, 
-    {
-        let v: &'static str = Box::leak(v.to_string().into_boxed_str());
-        Ok(Data {
-            data_type: DataType::STRING,
-            data: DataUnion { string: v },
-        })
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+
+    fn object(fields: Vec<(&str, Data)>) -> Data {
+        Data::Object(fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
     }
 
-    fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
-    where
-            E: serde::de::Error, 
-    {
-        let v: &'static str = Box::leak(v.into_boxed_str());
-        Ok(Data {
-            data_type: DataType::STRING,
-            data: DataUnion { string: v },
-        })
+    #[test]
+    fn serializes_every_kind_of_value() {
+        let json = |data: Data| serde_json::to_string(&data).unwrap();
+        assert_eq!(json(Data::String("Math".to_string())), r#""Math""#);
+        assert_eq!(json(Data::Integer(-7)), "-7");
+        assert_eq!(json(Data::Float(1.0)), "1.0");
+        assert_eq!(json(Data::Boolean(true)), "true");
+        assert_eq!(json(Data::Timestamp(datetime!(2022-05-02 08:00 +2))), r#""2022-05-02T08:00:00+02:00""#);
+        assert_eq!(json(Data::Bytes(b"hello".to_vec())), r#""aGVsbG8=""#);
+        assert_eq!(json(Data::Null), "null");
+        let nested = object(vec![
+            ("Lunch", object(vec![("Calories", Data::Integer(500)), ("Vegan", Data::Null)])),
+            ("Tags", Data::List(vec![Data::String("a".to_string()), Data::List(vec![]), Data::Float(0.5)])),
+        ]);
+        assert_eq!(json(nested), r#"{"Lunch":{"Calories":500,"Vegan":null},"Tags":["a",[],0.5]}"#);
+    }
+
+    #[test]
+    fn deserializes_the_serialized_shape() {
+        let data = |json: &str| serde_json::from_str::<Data>(json).unwrap();
+        assert_eq!(data(r#""Math""#), Data::String("Math".to_string()));
+        assert_eq!(data("-7"), Data::Integer(-7));
+        assert_eq!(data("1.0"), Data::Float(1.0));
+        assert_eq!(data("false"), Data::Boolean(false));
+        assert_eq!(data("null"), Data::Null);
+        assert!(serde_json::from_str::<Data>("18446744073709551615").is_err());
+
+        // Object fields keep their order, which is the order of the schema
+        let nested = object(vec![
+            ("Tags", Data::List(vec![Data::Integer(1), Data::Null, Data::List(vec![Data::Boolean(true)])])),
+            ("Lunch", object(vec![("Vegan", Data::Boolean(true)), ("Calories", Data::Integer(500))])),
+        ]);
+        let json = serde_json::to_string(&nested).unwrap();
+        assert_eq!(json, r#"{"Tags":[1,null,[true]],"Lunch":{"Vegan":true,"Calories":500}}"#);
+        assert_eq!(data(&json), nested);
+    }
+
+    #[test]
+    fn converts_strings_to_floats() {
+        assert_eq!(Data::String(" 0.5 ".to_string()).convert(&DataType::FLOAT), Some(Data::Float(0.5)));
+        assert_eq!(Data::String("-3".to_string()).convert(&DataType::FLOAT), Some(Data::Float(-3.0)));
+    }
+
+    #[test]
+    fn orders_numbers_exactly() {
+        let above = 2f64.powi(53);
+        assert_eq!(Data::Integer(2i64.pow(53) + 1).total_cmp(&Data::Float(above)), Ordering::Greater);
+        assert_eq!(Data::Float(above).total_cmp(&Data::Integer(2i64.pow(53) + 1)), Ordering::Less);
+        assert_eq!(Data::Integer(i64::MAX).total_cmp(&Data::Float(2f64.powi(63))), Ordering::Less);
+        assert_eq!(Data::Integer(3).total_cmp(&Data::Float(3.0)), Ordering::Equal);
+        assert_eq!(Data::Integer(0).total_cmp(&Data::Float(-0.0)), Ordering::Greater);
+        assert_eq!(Data::Float(f64::NAN).total_cmp(&Data::Integer(i64::MAX)), Ordering::Greater);
+        assert_eq!(Data::Float(-f64::NAN).total_cmp(&Data::Integer(i64::MIN)), Ordering::Less);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn orders_every_value_totally() {
+        let values = vec![
+            Data::Null,
+            Data::Float(f64::NAN),
+            Data::Float(-f64::NAN),
+            Data::Float(0.5),
+            Data::Float(-0.0),
+            Data::Float(0.0),
+            Data::Float(f64::INFINITY),
+            Data::Integer(0),
+            Data::Integer(1),
+            Data::Integer(-1),
+            Data::String("a".to_string()),
+            Data::Boolean(true),
+            Data::Bytes(vec![1]),
+            Data::List(vec![Data::Integer(1)]),
+            Data::List(vec![Data::Integer(1), Data::Null]),
+            Data::List(vec![Data::Float(f64::NAN)]),
+        ];
+        for a in values.iter() {
+            assert_eq!(a.total_cmp(a), Ordering::Equal, "{:?}", a);
+            for b in values.iter() {
+                assert_eq!(a.total_cmp(b), b.total_cmp(a).reverse(), "{:?} {:?}", a, b);
+                for c in values.iter() {
+                    if a.total_cmp(b).is_le() && b.total_cmp(c).is_le() {
+                        assert!(a.total_cmp(c).is_le(), "{:?} {:?} {:?}", a, b, c);
+                    }
+                }
+            }
+        }
+        assert_eq!(Data::Integer(7).total_cmp(&Data::String("1".to_string())), Ordering::Less);
+        assert_eq!(Data::Boolean(false).total_cmp(&Data::Null), Ordering::Less);
+    }
+
+    #[test]
+    fn rejects_non_finite_floats() {
+        for string in ["NaN", "inf", "-infinity", "1e400"] {
+            assert_eq!(Data::String(string.to_string()).convert(&DataType::FLOAT), None, "{}", string);
+        }
+    }
+
+    #[test]
+    fn restores_timestamps_and_bytes_from_strings() {
+        let timestamp = Data::Timestamp(datetime!(2022-05-02 08:00 UTC));
+        let bytes = Data::Bytes(vec![0, 255]);
+        for (value, data_type) in [(timestamp, DataType::TIMESTAMP), (bytes, DataType::BYTES)] {
+            let json = serde_json::to_string(&value).unwrap();
+            let read = serde_json::from_str::<Data>(&json).unwrap();
+            assert!(matches!(read, Data::String(_)), "{:?}", read);
+            assert_eq!(read.restore(&data_type, &[]), Ok(value));
+        }
+        assert!(Data::String("not base64!".to_string()).restore(&DataType::BYTES, &[]).is_err());
+        assert!(Data::String("yesterday".to_string()).restore(&DataType::TIMESTAMP, &[]).is_err());
+    }
+}
@@ -1,39 +1,196 @@
 use linked_hash_map::LinkedHashMap;
-use serde::{Serialize, Deserialize, de::Visitor};
+use serde::{Serialize, Deserialize};
 
-use super::serialization::{Data, DataType, DataUnion};
+use time::OffsetDateTime;
+
+use super::{serialization::{Data, DataType, restore_fields}, constraint::Constraint, history::Revision};
 
 /// The core structure of the in-memory values.
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct Template {
     pub template: Option<String>,
+    /// Name of the template this template inherits its fields from, only templates keep it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub instance: Option<String>,
-    pub data: LinkedHashMap<String, Data>
+    /// Raised by every change to the template, instances keep the version they are migrated to.
+    #[serde(default = "first_version")]
+    pub version: u32,
+    pub data: LinkedHashMap<String, Data>,
+    /// Types of the fields, only templates keep them.
+    #[serde(default, skip_serializing_if = "LinkedHashMap::is_empty")]
+    pub schema: LinkedHashMap<String, DataType>,
+    /// Constraints of the fields that have any, only templates keep them.
+    #[serde(default, skip_serializing_if = "LinkedHashMap::is_empty")]
+    pub constraints: LinkedHashMap<String, Vec<Constraint>>,
+    /// Every change made to the template since it was declared, only templates keep it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<Revision>,
+}
+
+/// Version of newly declared templates and of backups from before templates were versioned.
+fn first_version() -> u32 {
+    1
 }
 
 impl Template {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(name: String) -> TemplateBuilder {
         TemplateBuilder {
             template: Some(name),
+            extends: None,
             instance: None,
             data: None,
+            schema: LinkedHashMap::new(),
+            constraints: LinkedHashMap::new(),
         }
     }
 
-    pub fn instance(instance: String) -> TemplateBuilder {
-        TemplateBuilder {
-            template: None,
-            instance: Some(instance),
-            data: None,
+    /// Returns the value at a path like `Lunch.Calories` or `Tags[0]`,
+    /// which reaches into embedded templates and lists.
+    pub fn get(&self, path: &str) -> Option<&Data> {
+        let segments = segments(path)?;
+        let (first, rest) = segments.split_first()?;
+        let mut value = match first {
+            Segment::Field(name) => self.data.get(*name)?,
+            Segment::Index(_) => return None,
+        };
+        for segment in rest {
+            value = match (value, segment) {
+                (Data::Object(fields), Segment::Field(name)) => fields.get(*name)?,
+                (Data::List(items), Segment::Index(index)) => items.get(*index)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+
+    /// Mutable version of [`Template::get`].
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut Data> {
+        let segments = segments(path)?;
+        self.resolve_mut(&segments)
+    }
+
+    fn resolve_mut(&mut self, segments: &[Segment]) -> Option<&mut Data> {
+        let (first, rest) = segments.split_first()?;
+        let mut value = match first {
+            Segment::Field(name) => self.data.get_mut(*name)?,
+            Segment::Index(_) => return None,
+        };
+        for segment in rest {
+            value = match (value, segment) {
+                (Data::Object(fields), Segment::Field(name)) => fields.get_mut(*name)?,
+                (Data::List(items), Segment::Index(index)) => items.get_mut(*index)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+
+    /// Returns the type of the field at a path, the templates are needed to reach into embedded templates.
+    pub fn field_type<'a>(&'a self, path: &str, templates: &'a [Template]) -> Option<&'a DataType> {
+        let segments = segments(path)?;
+        let (first, rest) = segments.split_first()?;
+        let mut data_type = match first {
+            Segment::Field(name) => self.schema.get(*name)?,
+            Segment::Index(_) => return None,
+        };
+        for segment in rest {
+            data_type = match (data_type, segment) {
+                (DataType::TEMPLATE(template), Segment::Field(name)) => templates.iter()
+                    .find(|t| t.template.as_ref() == Some(template))?
+                    .schema
+                    .get(*name)?,
+                (DataType::LIST(element), Segment::Index(_)) => element,
+                _ => return None,
+            };
+        }
+        Some(data_type)
+    }
+
+    /// Sets the value at a path, returns false if the path doesn't lead to a field or an existing list item.
+    pub fn set(&mut self, path: &str, data: Data) -> bool {
+        let segments = match segments(path) {
+            Some(segments) => segments,
+            None => return false,
+        };
+        match segments.split_last() {
+            Some((Segment::Field(name), [])) => set_field(&mut self.data, name, data),
+            Some((last, parent)) => match (self.resolve_mut(parent), last) {
+                (Some(Data::Object(fields)), Segment::Field(name)) => set_field(fields, name, data),
+                (Some(Data::List(items)), Segment::Index(index)) => match items.get_mut(*index) {
+                    Some(item) => {
+                        *item = data;
+                        true
+                    },
+                    None => false,
+                },
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Brings the instance to the version of its template by applying every newer revision.
+    ///
+    /// Leaves the instance unchanged if any of them fails.
+    pub fn migrate(&mut self, template: &Template) -> Result<(), String> {
+        let mut migrated = self.clone();
+        for revision in template.history.iter().filter(|revision| revision.version > self.version) {
+            revision.apply(&mut migrated)?;
+        }
+        migrated.version = template.version;
+        *self = migrated;
+        Ok(())
+    }
+
+    /// Converts the values read from JSON back to the types of the schema.
+    pub fn restore_types(&mut self, schema: &LinkedHashMap<String, DataType>, templates: &[Template]) -> Result<(), String> {
+        restore_fields(&mut self.data, schema, templates)
+    }
+}
+
+/// A single step of a path, `Lunch.Tags[0]` consists of the fields `Lunch` and `Tags` and the index 0.
+enum Segment<'a> {
+    Field(&'a str),
+    Index(usize),
+}
+
+fn set_field(fields: &mut LinkedHashMap<String, Data>, name: &str, data: Data) -> bool {
+    match fields.get_mut(name) {
+        // Overwrite in place to keep the order of the fields
+        Some(value) => *value = data,
+        None => { fields.insert(name.to_string(), data); },
+    }
+    true
+}
+
+/// Splits a path into its segments, returns `None` if an index is malformed.
+fn segments(path: &str) -> Option<Vec<Segment<'_>>> {
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (name, mut indices) = part.split_at(part.find('[').unwrap_or(part.len()));
+        segments.push(Segment::Field(name));
+        while let Some(rest) = indices.strip_prefix('[') {
+            let (index, rest) = rest.split_once(']')?;
+            segments.push(Segment::Index(index.parse().ok()?));
+            indices = rest;
+        }
+        if !indices.is_empty() {
+            return None;
         }
     }
+    Some(segments)
 }
 
 pub struct TemplateBuilder {
     template: Option<String>,
+    extends: Option<String>,
     instance: Option<String>,
-    data: Option<LinkedHashMap<String, Data>>
+    data: Option<LinkedHashMap<String, Data>>,
+    schema: LinkedHashMap<String, DataType>,
+    constraints: LinkedHashMap<String, Vec<Constraint>>,
 }
 
 impl TemplateBuilder {
@@ -43,39 +200,78 @@ impl TemplateBuilder {
     
         Template {
             template: self.template,
-            instance: None,
+            extends: self.extends,
+            instance: self.instance,
+            version: first_version(),
             data,
+            schema: self.schema,
+            constraints: self.constraints,
+            history: Vec::new(),
         }
     }
 
-    pub fn with_name(&self, name: String) -> Self {
-        Self {
-            template: self.template.clone(),
-            instance: Some(name),
-            data: self.data.clone()
-        }
+    /// Inherits the fields of the parent template with their starting values and constraints.
+    pub fn extends(mut self, parent: &Template) -> Self {
+        self.extends = parent.template.clone();
+        self.data = Some(parent.data.clone());
+        self.schema = parent.schema.clone();
+        self.constraints = parent.constraints.clone();
+        self
     }
 
-    fn with(&self, name: String, data: DataUnion, data_type: DataType) -> Self {
-        let value = Data { data_type, data };
-        let mut data = self.data.clone().unwrap_or_default();
-        data.insert(name, value);
-        Self {
-            template: self.template.clone(),
-            instance: self.instance.clone(),
-            data: Some(data),
-        }
+    pub fn has_field(&self, name: &str) -> bool {
+        self.schema.contains_key(name)
+    }
+
+    /// Adds a field of the given type, the data has to be of that type.
+    pub fn with_data(mut self, name: String, data_type: DataType, data: Data) -> Self {
+        self.schema.insert(name.clone(), data_type);
+        self.data.get_or_insert_with(LinkedHashMap::new).insert(name, data);
+        self
     }
 
-    pub fn with_string(&self, name: String, string: Option<String>) -> Self {
-        self.with(name, string.into(), DataType::STRING)
+    // Fields without a value are null
+
+    pub fn with_string(self, name: String, string: Option<String>) -> Self {
+        self.with_data(name, DataType::STRING, string.map(Data::String).unwrap_or(Data::Null))
+    }
+
+    pub fn with_integer(self, name: String, int: Option<i64>) -> Self {
+        self.with_data(name, DataType::INTEGER, int.map(Data::Integer).unwrap_or(Data::Null))
+    }
+
+    pub fn with_float(self, name: String, float: Option<f64>) -> Self {
+        self.with_data(name, DataType::FLOAT, float.map(Data::Float).unwrap_or(Data::Null))
+    }
+
+    pub fn with_boolean(self, name: String, boolean: Option<bool>) -> Self {
+        self.with_data(name, DataType::BOOLEAN, boolean.map(Data::Boolean).unwrap_or(Data::Null))
+    }
+
+    pub fn with_timestamp(self, name: String, timestamp: Option<OffsetDateTime>) -> Self {
+        self.with_data(name, DataType::TIMESTAMP, timestamp.map(Data::Timestamp).unwrap_or(Data::Null))
+    }
+
+    pub fn with_bytes(self, name: String, bytes: Option<Vec<u8>>) -> Self {
+        self.with_data(name, DataType::BYTES, bytes.map(Data::Bytes).unwrap_or(Data::Null))
+    }
+
+    /// Adds constraints to the field, fields without any don't show up in the constraints.
+    pub fn with_constraints(mut self, name: String, constraints: Vec<Constraint>) -> Self {
+        if !constraints.is_empty() {
+            self.constraints.insert(name, constraints);
+        }
+        self
     }
 
-    pub fn with_integer(&self, name: String, int: Option<i64>) -> Self {
-        self.with(name, int.into(), DataType::INTEGER)
+    /// Adds an empty list of the given element type.
+    pub fn with_list(self, name: String, element: DataType) -> Self {
+        self.with_data(name, DataType::LIST(Box::new(element)), Data::List(Vec::new()))
     }
 
-    pub fn with_float(&self, name: String, float: Option<f64>) -> Self {
-        self.with(name, float.into(), DataType::FLOAT)
+    /// Embeds the other template, starting with its starting values.
+    pub fn with_template(self, name: String, template: &Template) -> Self {
+        let data_type = DataType::TEMPLATE(template.template.clone().unwrap_or_default());
+        self.with_data(name, data_type, Data::Object(template.data.clone()))
     }
 }
\ No newline at end of file
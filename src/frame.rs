@@ -0,0 +1,158 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::crypto::{Key, DecryptionError, OVERHEAD};
+
+/// Frames with a bigger payload are rejected, before anything gets allocated for them.
+pub const MAX_PAYLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+/// Limit for the frames of the handshake, which only carry a user name or a proof.
+///
+/// Clients that didn't authenticate yet can't make the server hold on to more than this.
+pub const MAX_HANDSHAKE_SIZE: usize = 4 * 1024;
+
+/// Length of the header in front of every payload.
+pub const HEADER_SIZE: usize = 9;
+
+/// A single message on the wire.
+///
+/// Every frame starts with a header of the length of its payload and the id of the request, both as big-endian [`u32`],
+/// and the id of the key the payload is encrypted with, followed by the payload itself.
+/// Responses carry the request and key id of the request they answer.
+///
+/// The header is authenticated along with the payload, so neither can be changed on the way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub request_id: u32,
+    pub key_id: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(request_id: u32, key_id: u8, payload: Vec<u8>) -> Self {
+        Self { request_id, key_id, payload }
+    }
+
+    /// Encrypts the message into a new frame.
+    pub fn seal(request_id: u32, key_id: u8, key: &Key, message: Vec<u8>) -> Self {
+        let header = header(message.len() + OVERHEAD, request_id, key_id);
+        Self::new(request_id, key_id, key.encrypt(message, &header))
+    }
+
+    /// Decrypts the payload, fails if the header or the payload were tampered with.
+    pub fn open(self, key: &Key) -> Result<Vec<u8>, DecryptionError> {
+        let header = self.header();
+        key.decrypt(self.payload, &header)
+    }
+
+    fn header(&self) -> [u8; HEADER_SIZE] {
+        header(self.payload.len(), self.request_id, self.key_id)
+    }
+
+    /// Reads a whole frame, no matter in how many segments it arrives, and fails if its payload exceeds `max_size`.
+    ///
+    /// The payload only grows as its bytes arrive, so a header alone can't make the server allocate `max_size`.
+    /// Returns `None` if the stream was closed before a new frame started.
+    pub async fn read<R: AsyncRead + Unpin>(reader: &mut R, max_size: usize) -> io::Result<Option<Frame>> {
+        let length = match reader.read_u32().await {
+            Ok(length) => length as usize,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if length > max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Frame of {} bytes exceeds the maximum of {} bytes", length, max_size),
+            ));
+        }
+        let request_id = reader.read_u32().await?;
+        let key_id = reader.read_u8().await?;
+        let mut payload = Vec::new();
+        reader.take(length as u64).read_to_end(&mut payload).await?;
+        if payload.len() < length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Stream closed in the middle of a frame"));
+        }
+        Ok(Some(Frame { request_id, key_id, payload }))
+    }
+
+    /// Writes the header and the payload.
+    pub async fn write<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> io::Result<()> {
+        if u32::try_from(self.payload.len()).is_err() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Payload is too big for a single frame"));
+        }
+        writer.write_all(&self.header()).await?;
+        writer.write_all(&self.payload).await?;
+        writer.flush().await
+    }
+}
+
+/// The length of the payload and the request id as big-endian [`u32`], followed by the key id.
+fn header(length: usize, request_id: u32, key_id: u8) -> [u8; HEADER_SIZE] {
+    let mut header = [0u8; HEADER_SIZE];
+    header[..4].copy_from_slice(&(length as u32).to_be_bytes());
+    header[4..8].copy_from_slice(&request_id.to_be_bytes());
+    header[8] = key_id;
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use crate::crypto::Kdf;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trip_over_split_reads() {
+        // The tiny buffer splits every frame into many reads
+        let (mut client, mut server) = duplex(3);
+        let frames = vec![
+            Frame::new(1, 0, b"QUERY TYPE;".to_vec()),
+            Frame::new(2, 7, vec![42u8; 1000]),
+            Frame::new(u32::MAX, 255, Vec::new()),
+        ];
+        let sent = frames.clone();
+        let writer = tokio::spawn(async move {
+            for frame in sent {
+                frame.write(&mut client).await.unwrap();
+            }
+        });
+        for frame in frames {
+            assert_eq!(Frame::read(&mut server, MAX_PAYLOAD_SIZE).await.unwrap(), Some(frame));
+        }
+        writer.await.unwrap();
+        assert_eq!(Frame::read(&mut server, MAX_PAYLOAD_SIZE).await.unwrap(), None);
+    }
+
+    #[test]
+    fn header_is_authenticated() {
+        let key = Key::derive(b"secret", &Kdf::new("test".to_string(), 1));
+        let frame = Frame::seal(5, 1, &key, b"QUERY TYPE;".to_vec());
+        assert_eq!(frame.clone().open(&key).unwrap(), b"QUERY TYPE;");
+        assert!(Frame { request_id: 6, ..frame.clone() }.open(&key).is_err());
+        assert!(Frame { key_id: 0, ..frame }.open(&key).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_frames() {
+        let header = [((MAX_PAYLOAD_SIZE + 1) as u32).to_be_bytes().as_slice(), &[0u8; 5]].concat();
+        let err = Frame::read(&mut header.as_slice(), MAX_PAYLOAD_SIZE).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut bytes = Vec::new();
+        Frame::new(1, 0, vec![0u8; MAX_HANDSHAKE_SIZE + 1]).write(&mut bytes).await.unwrap();
+        let err = Frame::read(&mut bytes.as_slice(), MAX_HANDSHAKE_SIZE).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(Frame::read(&mut bytes.as_slice(), MAX_PAYLOAD_SIZE).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_frames() {
+        // The header announces the largest payload, but the stream ends after a few bytes of it
+        let bytes = [(MAX_PAYLOAD_SIZE as u32).to_be_bytes().as_slice(), &[0u8; 5], b"short"].concat();
+        let err = Frame::read(&mut bytes.as_slice(), MAX_PAYLOAD_SIZE).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
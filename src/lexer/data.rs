@@ -1,10 +1,11 @@
-use std::ops::{Range, Sub};
+use std::ops::Range;
 
 use regex::Regex;
 
 /// The different Keywords used in PANG
-/// 
-/// It has 19 Tokens in total
+///
+/// It has 78 Tokens in total
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Token {
     Remove,
@@ -21,11 +22,157 @@ pub enum Token {
     StringType,
     IntegerType,
     FloatType,
+    BooleanType,
+    TimestampType,
+    BytesType,
+    List,
+    Of,
+    Append,
+    True,
+    False,
+    Null,
+    Where,
+    Is,
+    Not,
+    Alter,
+    Add,
+    Drop,
+    Rename,
+    Modify,
+    History,
+    Extends,
+    Derived,
+    And,
+    Or,
+    Like,
+    In,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    OpenParen,
+    CloseParen,
+    Comma,
+    Order,
+    By,
+    Asc,
+    Desc,
+    Limit,
+    Offset,
+    Cursor,
+    After,
+    Count,
+    Sum,
+    Avg,
+    Group,
     Starting,
+    Required,
+    Unique,
+    Min,
+    Max,
+    Matches,
+    MaxLen,
+    Grant,
+    Revoke,
+    On,
+    To,
+    From,
+    Read,
+    Write,
     ENDL,
     Literal,
     Integer,
     Float,
+    /// Any character that isn't part of another token.
+    Unknown,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Token::Remove => "REMOVE",
+            Token::Delete => "DELETE",
+            Token::Then => "THEN",
+            Token::Value => "VALUE",
+            Token::Type => "TYPE",
+            Token::Name => "NAME",
+            Token::End => "END",
+            Token::Create => "CREATE",
+            Token::Query => "QUERY",
+            Token::Set => "SET",
+            Token::Get => "GET",
+            Token::StringType => "STRING",
+            Token::IntegerType => "INTEGER",
+            Token::FloatType => "FLOAT",
+            Token::BooleanType => "BOOLEAN",
+            Token::TimestampType => "TIMESTAMP",
+            Token::BytesType => "BYTES",
+            Token::List => "LIST",
+            Token::Of => "OF",
+            Token::Append => "APPEND",
+            Token::True => "TRUE",
+            Token::False => "FALSE",
+            Token::Null => "NULL",
+            Token::Where => "WHERE",
+            Token::Is => "IS",
+            Token::Not => "NOT",
+            Token::Alter => "ALTER",
+            Token::Add => "ADD",
+            Token::Drop => "DROP",
+            Token::Rename => "RENAME",
+            Token::Modify => "MODIFY",
+            Token::History => "HISTORY",
+            Token::Extends => "EXTENDS",
+            Token::Derived => "DERIVED",
+            Token::And => "AND",
+            Token::Or => "OR",
+            Token::Like => "LIKE",
+            Token::In => "IN",
+            Token::Equal => "=",
+            Token::NotEqual => "!=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::OpenParen => "(",
+            Token::CloseParen => ")",
+            Token::Comma => ",",
+            Token::Order => "ORDER",
+            Token::By => "BY",
+            Token::Asc => "ASC",
+            Token::Desc => "DESC",
+            Token::Limit => "LIMIT",
+            Token::Offset => "OFFSET",
+            Token::Cursor => "CURSOR",
+            Token::After => "AFTER",
+            Token::Count => "COUNT",
+            Token::Sum => "SUM",
+            Token::Avg => "AVG",
+            Token::Group => "GROUP",
+            Token::Starting => "STARTING",
+            Token::Required => "REQUIRED",
+            Token::Unique => "UNIQUE",
+            Token::Min => "MIN",
+            Token::Max => "MAX",
+            Token::Matches => "MATCHES",
+            Token::MaxLen => "MAXLEN",
+            Token::Grant => "GRANT",
+            Token::Revoke => "REVOKE",
+            Token::On => "ON",
+            Token::To => "TO",
+            Token::From => "FROM",
+            Token::Read => "READ",
+            Token::Write => "WRITE",
+            Token::ENDL => ";",
+            Token::Literal => "string literal",
+            Token::Integer => "integer",
+            Token::Float => "float",
+            Token::Unknown => "unknown character",
+        };
+        f.write_str(name)
+    }
 }
 
 /// Defines where a given [`TokenDefinition`] has matched the source
@@ -35,7 +182,18 @@ pub struct TokenMatch {
     pub value: String,
     pub start: usize,
     pub end: usize,
-    pub priority: u8,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl std::fmt::Display for TokenMatch {
+    /// Writes the token as it appeared in the source.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.token {
+            Token::Literal => write!(f, "\"{}\"", self.value),
+            _ => f.write_str(&self.value),
+        }
+    }
 }
 
 /// Adds extra information to [`Token`]
@@ -47,10 +205,10 @@ pub struct TokenDefinition {
 }
 
 impl TokenDefinition {
-    /// Creates a new TokenDefinition   
+    /// Creates a new TokenDefinition
     /// based on a [`Token`], a `Regex` string to match the token against and a priority.
-    /// 
-    /// Every TokenDefinition gets sorted based on their priority and those with higher priority 
+    ///
+    /// Every TokenDefinition gets sorted based on their priority and those with higher priority
     /// will get processed first in the parsing process.
     pub fn new(token: Token, regex: &str, priority: u8) -> Self {
         Self {
@@ -61,29 +219,31 @@ impl TokenDefinition {
     }
 
     /// Match the source string against the TokenDefinition
-    pub fn match_text(&self, source: &String, already_matched: &mut Vec<TokenMatch>) {
+    ///
+    /// Captures overlapping with an already matched token are discarded.
+    pub fn match_text(&self, source: &str, already_matched: &mut Vec<TokenMatch>) {
+        // Matches never overlap, so sorting them by start also sorts them by end
+        already_matched.sort_by_key(|a_match| a_match.start);
         let mut result: Vec<TokenMatch> = Vec::new();
-        let captures = self.regex.captures_iter(source);
-        for capture in captures {
-            if capture.get(0).is_some() {
-                let capture = capture.get(0).unwrap();
-                let mut exists = false;
+        for capture in self.regex.find_iter(source) {
+            // The last match starting before the capture ends is the only one that could overlap
+            let index = already_matched.partition_point(|a_match| a_match.start < capture.end());
+            let exists = index > 0 && Self::range_overlap(
+                capture.start()..capture.end(),
+                already_matched[index-1].start..already_matched[index-1].end,
+            );
 
-                already_matched.iter().for_each(|a_match| {
-                    exists = Self::range_overlap(capture.start()..capture.end(), a_match.start..a_match.end);
-                });
-                
-                if !exists {
-                    result.push(
-                        TokenMatch {
-                            token: self.token.clone(),
-                            value: capture.as_str().to_owned(),
-                            start: capture.start(),
-                            end: capture.end(),
-                            priority: self.priority,
-                        }
-                    );
-                }
+            if !exists {
+                result.push(
+                    TokenMatch {
+                        token: self.token,
+                        value: capture.as_str().to_owned(),
+                        start: capture.start(),
+                        end: capture.end(),
+                        line: 0,
+                        column: 0,
+                    }
+                );
             }
         }
         already_matched.append(&mut result);
@@ -91,7 +251,6 @@ impl TokenDefinition {
 
     /// Returns true if first [`Range`] is overlapping with the second [`Range`]
     fn range_overlap(first: Range<usize>, second: Range<usize>) -> bool {
-        (first.start >= second.start && first.start <= second.end) ||
-        (first.end-1 >= second.start && first.end-1 <= second.end)
+        first.start < second.end && second.start < first.end
     }
-}
\ No newline at end of file
+}
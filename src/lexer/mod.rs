@@ -9,42 +9,35 @@ lazy_static! {
     pub static ref TOKEN_DEFINITIONS: Vec<TokenDefinition> = tokens::initialize();
 }
 
-/// Parses a source [`String`] and returns a [`Vec<Vec<TokenMatch>>`] with each outer vector containing a list of TokenMatches, 
-/// which are the Tokens in the lines from the source.
-pub fn parse(mut source: String) -> Vec<Vec<TokenMatch>> {
-    source.push_str("\n");
+/// Lexes a source [`String`] and returns every [`TokenMatch`] in the order they appear in the source.
+///
+/// Statements are separated by [`Token::ENDL`] matches, literals are returned without their quotes.
+/// Every match knows the line and column it starts at, both counting from 1.
+pub fn parse(source: String) -> Vec<TokenMatch> {
     let mut token_matches: Vec<TokenMatch> = Vec::new();
     TOKEN_DEFINITIONS.iter()
-        .for_each(|definition| 
+        .for_each(|definition|
             definition.match_text(&source, &mut token_matches)
         );
-    token_matches.sort_by(|a, b| a.start.cmp(&b.start));
+    token_matches.sort_by_key(|a| a.start);
 
-    let mut lines = Vec::new();
-    token_matches.split(|m| m.token == Token::ENDL)
-    .collect::<Vec<&[TokenMatch]>>().iter()
-    .for_each(|tms| {
-        let mut line = Vec::new();
-        for tm in *tms {
-            let tm = match tm.token {
-                Token::Literal => {
-                    let value = tm.value.to_string()
-                        .strip_prefix("\"").unwrap().to_string()
-                        .strip_suffix("\"").unwrap().to_string();
-                    let mut ctm = tm.clone();
-                    ctm.value = value;
-                    ctm
-                },
-                _ => tm.clone(),
-            };
-            line.push(tm.clone());
+    let mut chars = source.char_indices().peekable();
+    let (mut line, mut column) = (1, 1);
+    for tm in token_matches.iter_mut() {
+        while let Some((_, c)) = chars.next_if(|(index, _)| *index < tm.start) {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        tm.line = line;
+        tm.column = column;
+
+        if tm.token == Token::Literal {
+            tm.value = tm.value[1..tm.value.len()-1].to_string();
         }
-        lines.push(line);
-    });
-    let last = lines.remove(lines.len()-1);
-    if last.len() == 0 {
-        return lines
     }
-    lines.push(last);
-    lines
-}
\ No newline at end of file
+    token_matches
+}
@@ -1,29 +1,90 @@
+use std::cmp::Reverse;
+
 use super::data::{Token, TokenDefinition};
 
 /// Initializes every [`TokenDefinition`] and defines the regex for each.
 pub fn initialize() -> Vec<TokenDefinition> {
-    let mut token_definitions = Vec::<TokenDefinition>::with_capacity(13);
-
-    token_definitions.push(TokenDefinition::new(Token::Query, r"QUERY", 1));
-    token_definitions.push(TokenDefinition::new(Token::Delete, r"DELETE", 1));
-    token_definitions.push(TokenDefinition::new(Token::Remove, r"REMOVE", 1));
-    token_definitions.push(TokenDefinition::new(Token::Then, r"THEN", 1));
-    token_definitions.push(TokenDefinition::new(Token::Value, r#"VALUE"#, 1));
-    token_definitions.push(TokenDefinition::new(Token::Create, r"CREATE", 1));
-    token_definitions.push(TokenDefinition::new(Token::End, r"END", 1));
-    token_definitions.push(TokenDefinition::new(Token::Type, r"TYPE", 1));
-    token_definitions.push(TokenDefinition::new(Token::ENDL, r";", 1));
-    token_definitions.push(TokenDefinition::new(Token::Name, r"NAME", 1));
-    token_definitions.push(TokenDefinition::new(Token::Set, r"SET", 1));
-    token_definitions.push(TokenDefinition::new(Token::Get, r"GET", 1));
-    token_definitions.push(TokenDefinition::new(Token::StringType, r"STRING", 1));
-    token_definitions.push(TokenDefinition::new(Token::IntegerType, r"INTEGER", 1));
-    token_definitions.push(TokenDefinition::new(Token::FloatType, r"FLOAT", 1));
-    token_definitions.push(TokenDefinition::new(Token::Starting, r"STARTING", 1));
-    token_definitions.push(TokenDefinition::new(Token::Integer, r#"\d+"#, 2));
-    token_definitions.push(TokenDefinition::new(Token::Float, r#"\d+\.\d+"#, 3));
-    token_definitions.push(TokenDefinition::new(Token::Literal, r#""[^"]+""#, 4));
+    let mut token_definitions = vec![
+        TokenDefinition::new(Token::Query, r"\bQUERY\b", 1),
+        TokenDefinition::new(Token::Delete, r"\bDELETE\b", 1),
+        TokenDefinition::new(Token::Remove, r"\bREMOVE\b", 1),
+        TokenDefinition::new(Token::Then, r"\bTHEN\b", 1),
+        TokenDefinition::new(Token::Value, r"\bVALUE\b", 1),
+        TokenDefinition::new(Token::Create, r"\bCREATE\b", 1),
+        TokenDefinition::new(Token::End, r"\bEND\b", 1),
+        TokenDefinition::new(Token::Type, r"\bTYPE\b", 1),
+        TokenDefinition::new(Token::ENDL, r";", 1),
+        TokenDefinition::new(Token::Name, r"\bNAME\b", 1),
+        TokenDefinition::new(Token::Set, r"\bSET\b", 1),
+        TokenDefinition::new(Token::Get, r"\bGET\b", 1),
+        TokenDefinition::new(Token::StringType, r"\bSTRING\b", 1),
+        TokenDefinition::new(Token::IntegerType, r"\bINTEGER\b", 1),
+        TokenDefinition::new(Token::FloatType, r"\bFLOAT\b", 1),
+        TokenDefinition::new(Token::BooleanType, r"\bBOOLEAN\b", 1),
+        TokenDefinition::new(Token::TimestampType, r"\bTIMESTAMP\b", 1),
+        TokenDefinition::new(Token::BytesType, r"\bBYTES\b", 1),
+        TokenDefinition::new(Token::List, r"\bLIST\b", 1),
+        TokenDefinition::new(Token::Of, r"\bOF\b", 1),
+        TokenDefinition::new(Token::Append, r"\bAPPEND\b", 1),
+        TokenDefinition::new(Token::True, r"\bTRUE\b", 1),
+        TokenDefinition::new(Token::False, r"\bFALSE\b", 1),
+        TokenDefinition::new(Token::Null, r"\bNULL\b", 1),
+        TokenDefinition::new(Token::Where, r"\bWHERE\b", 1),
+        TokenDefinition::new(Token::Is, r"\bIS\b", 1),
+        TokenDefinition::new(Token::Not, r"\bNOT\b", 1),
+        TokenDefinition::new(Token::Alter, r"\bALTER\b", 1),
+        TokenDefinition::new(Token::Add, r"\bADD\b", 1),
+        TokenDefinition::new(Token::Drop, r"\bDROP\b", 1),
+        TokenDefinition::new(Token::Rename, r"\bRENAME\b", 1),
+        TokenDefinition::new(Token::Modify, r"\bMODIFY\b", 1),
+        TokenDefinition::new(Token::History, r"\bHISTORY\b", 1),
+        TokenDefinition::new(Token::Extends, r"\bEXTENDS\b", 1),
+        TokenDefinition::new(Token::Derived, r"\bDERIVED\b", 1),
+        TokenDefinition::new(Token::And, r"\bAND\b", 1),
+        TokenDefinition::new(Token::Or, r"\bOR\b", 1),
+        TokenDefinition::new(Token::Like, r"\bLIKE\b", 1),
+        TokenDefinition::new(Token::In, r"\bIN\b", 1),
+        TokenDefinition::new(Token::Equal, r"=", 1),
+        TokenDefinition::new(Token::NotEqual, r"!=", 2),
+        TokenDefinition::new(Token::Less, r"<", 1),
+        TokenDefinition::new(Token::LessEqual, r"<=", 2),
+        TokenDefinition::new(Token::Greater, r">", 1),
+        TokenDefinition::new(Token::GreaterEqual, r">=", 2),
+        TokenDefinition::new(Token::OpenParen, r"\(", 1),
+        TokenDefinition::new(Token::CloseParen, r"\)", 1),
+        TokenDefinition::new(Token::Comma, r",", 1),
+        TokenDefinition::new(Token::Order, r"\bORDER\b", 1),
+        TokenDefinition::new(Token::By, r"\bBY\b", 1),
+        TokenDefinition::new(Token::Asc, r"\bASC\b", 1),
+        TokenDefinition::new(Token::Desc, r"\bDESC\b", 1),
+        TokenDefinition::new(Token::Limit, r"\bLIMIT\b", 1),
+        TokenDefinition::new(Token::Offset, r"\bOFFSET\b", 1),
+        TokenDefinition::new(Token::Cursor, r"\bCURSOR\b", 1),
+        TokenDefinition::new(Token::After, r"\bAFTER\b", 1),
+        TokenDefinition::new(Token::Count, r"\bCOUNT\b", 1),
+        TokenDefinition::new(Token::Sum, r"\bSUM\b", 1),
+        TokenDefinition::new(Token::Avg, r"\bAVG\b", 1),
+        TokenDefinition::new(Token::Group, r"\bGROUP\b", 1),
+        TokenDefinition::new(Token::Starting, r"\bSTARTING\b", 1),
+        TokenDefinition::new(Token::Required, r"\bREQUIRED\b", 1),
+        TokenDefinition::new(Token::Unique, r"\bUNIQUE\b", 1),
+        TokenDefinition::new(Token::Min, r"\bMIN\b", 1),
+        TokenDefinition::new(Token::Max, r"\bMAX\b", 1),
+        TokenDefinition::new(Token::Matches, r"\bMATCHES\b", 1),
+        TokenDefinition::new(Token::MaxLen, r"\bMAXLEN\b", 1),
+        TokenDefinition::new(Token::Grant, r"\bGRANT\b", 1),
+        TokenDefinition::new(Token::Revoke, r"\bREVOKE\b", 1),
+        TokenDefinition::new(Token::On, r"\bON\b", 1),
+        TokenDefinition::new(Token::To, r"\bTO\b", 1),
+        TokenDefinition::new(Token::From, r"\bFROM\b", 1),
+        TokenDefinition::new(Token::Read, r"\bREAD\b", 1),
+        TokenDefinition::new(Token::Write, r"\bWRITE\b", 1),
+        TokenDefinition::new(Token::Integer, r"-?\d+", 2),
+        TokenDefinition::new(Token::Float, r"-?\d+\.\d+", 3),
+        TokenDefinition::new(Token::Literal, r#""[^"]*""#, 4),
+        TokenDefinition::new(Token::Unknown, r"\S", 0),
+    ];
 
-    token_definitions.sort_by(|a, b| a.priority.cmp(&b.priority));
+    token_definitions.sort_by_key(|definition| Reverse(definition.priority));
     token_definitions
-}
\ No newline at end of file
+}
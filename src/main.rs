@@ -11,6 +11,8 @@ mod backup;
 mod util;
 mod server;
 mod crypto;
+mod auth;
+mod frame;
 
 #[tokio::main]
 async fn main() {
@@ -0,0 +1,248 @@
+use std::cmp::Ordering;
+
+use linked_hash_map::LinkedHashMap;
+use serde::Serialize;
+
+use crate::data::{template::Template, serialization::{Data, DataType}};
+
+use super::{ast::{AggregateQuery, Aggregate, SortKey}, error::RequestError, page::compare_values};
+
+/// The result of an aggregate query, with a group for every combination of values of the `GROUP BY` fields.
+#[derive(Serialize, Debug)]
+pub struct Aggregation {
+    pub template: String,
+    pub groups: Vec<Group>,
+}
+
+/// The aggregates of a group, e.g. `{"group": {"First": "Science"}, "COUNT": 2, "AVG": {"Seconds": 0.5}}`.
+///
+/// Aggregates of fields without any values are `null`.
+#[derive(Serialize, Debug, Default)]
+pub struct Group {
+    /// The values of the `GROUP BY` fields, shared by every instance of the group.
+    pub group: LinkedHashMap<String, Data>,
+    #[serde(rename = "COUNT", skip_serializing_if = "Option::is_none")]
+    pub count: Option<i64>,
+    #[serde(rename = "SUM", skip_serializing_if = "LinkedHashMap::is_empty")]
+    pub sum: LinkedHashMap<String, Data>,
+    #[serde(rename = "AVG", skip_serializing_if = "LinkedHashMap::is_empty")]
+    pub avg: LinkedHashMap<String, Data>,
+    #[serde(rename = "MIN", skip_serializing_if = "LinkedHashMap::is_empty")]
+    pub min: LinkedHashMap<String, Data>,
+    #[serde(rename = "MAX", skip_serializing_if = "LinkedHashMap::is_empty")]
+    pub max: LinkedHashMap<String, Data>,
+}
+
+impl AggregateQuery {
+    /// Makes sure that every field is part of the template and fits its aggregate.
+    pub fn check(&self, template: &Template, templates: &[Template]) -> Result<(), RequestError> {
+        let field_type = |field: &String| template.field_type(field, templates)
+            .ok_or_else(|| RequestError::FieldNonExistent(field.clone()));
+        for field in self.groups.iter() {
+            if let data_type @ (DataType::TEMPLATE(_) | DataType::LIST(_)) = field_type(field)? {
+                return Err(RequestError::TypeMismatch(format!(
+                    "Instances can't be grouped by field \"{}\" of type {}.", field, data_type,
+                )));
+            }
+        }
+        for aggregate in self.aggregates.iter() {
+            match aggregate {
+                Aggregate::Count => {},
+                Aggregate::Sum(field) | Aggregate::Avg(field) => match field_type(field)? {
+                    DataType::INTEGER | DataType::FLOAT => {},
+                    data_type => return Err(RequestError::TypeMismatch(format!(
+                        "SUM and AVG only apply to numbers, field \"{}\" is of type {}.", field, data_type,
+                    ))),
+                },
+                Aggregate::Min(field) | Aggregate::Max(field) => {
+                    if let data_type @ (DataType::TEMPLATE(_) | DataType::LIST(_)) = field_type(field)? {
+                        return Err(RequestError::TypeMismatch(format!(
+                            "MIN and MAX don't apply to field \"{}\" of type {}.", field, data_type,
+                        )));
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Groups the instances by the values of the `GROUP BY` fields and aggregates every group.
+    ///
+    /// Groups are ordered by their values, without `GROUP BY` there is a single group even if there are no instances.
+    pub fn aggregate(&self, instances: Vec<&Template>) -> Result<Vec<Group>, RequestError> {
+        let keys = self.groups.iter()
+            .map(|field| SortKey { field: field.clone(), descending: false })
+            .collect::<Vec<SortKey>>();
+        let mut rows = instances.into_iter()
+            .map(|instance| {
+                let values = self.groups.iter()
+                    .map(|field| instance.get(field).cloned().unwrap_or(Data::Null))
+                    .collect::<Vec<Data>>();
+                (values, instance)
+            })
+            .collect::<Vec<(Vec<Data>, &Template)>>();
+        rows.sort_by(|(a, _), (b, _)| compare_values(a, b, &keys));
+
+        let mut groups: Vec<(Vec<Data>, Vec<&Template>)> = Vec::new();
+        for (values, instance) in rows {
+            match groups.last_mut() {
+                Some((group, members)) if compare_values(group, &values, &keys) == Ordering::Equal => members.push(instance),
+                _ => groups.push((values, vec![instance])),
+            }
+        }
+        if groups.is_empty() && self.groups.is_empty() {
+            groups.push((Vec::new(), Vec::new()));
+        }
+        groups.into_iter()
+            .map(|(values, members)| self.summarize(values, &members))
+            .collect()
+    }
+
+    fn summarize(&self, values: Vec<Data>, members: &[&Template]) -> Result<Group, RequestError> {
+        let mut group = Group {
+            group: self.groups.iter().cloned().zip(values).collect(),
+            ..Group::default()
+        };
+        // The values of the field in the group, null values are left out
+        let column = |field: &String| members.iter()
+            .filter_map(|instance| instance.get(field))
+            .filter(|value| **value != Data::Null)
+            .collect::<Vec<&Data>>();
+        for aggregate in self.aggregates.iter() {
+            match aggregate {
+                Aggregate::Count => group.count = Some(members.len() as i64),
+                Aggregate::Sum(field) => {
+                    group.sum.insert(field.clone(), sum(field, column(field))?);
+                },
+                Aggregate::Avg(field) => {
+                    let values = column(field);
+                    let avg = match values.len() {
+                        0 => Data::Null,
+                        count => Data::Float(values.into_iter().map(number).sum::<f64>() / count as f64),
+                    };
+                    group.avg.insert(field.clone(), avg);
+                },
+                Aggregate::Min(field) => {
+                    let min = column(field).into_iter().min_by(|a, b| a.total_cmp(b));
+                    group.min.insert(field.clone(), min.cloned().unwrap_or(Data::Null));
+                },
+                Aggregate::Max(field) => {
+                    let max = column(field).into_iter().max_by(|a, b| a.total_cmp(b));
+                    group.max.insert(field.clone(), max.cloned().unwrap_or(Data::Null));
+                },
+            }
+        }
+        Ok(group)
+    }
+}
+
+/// Sums integers as integer and anything else as float, fails if a sum of integers overflows.
+fn sum(field: &str, values: Vec<&Data>) -> Result<Data, RequestError> {
+    if values.is_empty() {
+        return Ok(Data::Null);
+    }
+    if values.iter().all(|value| matches!(value, Data::Integer(_))) {
+        return values.iter()
+            .try_fold(0i64, |total, value| match value {
+                Data::Integer(int) => total.checked_add(*int),
+                _ => Some(total),
+            })
+            .map(Data::Integer)
+            .ok_or_else(|| RequestError::TypeMismatch(format!(
+                "The sum of field \"{}\" is too large for an INTEGER.", field,
+            )));
+    }
+    Ok(Data::Float(values.into_iter().map(number).sum()))
+}
+
+fn number(value: &Data) -> f64 {
+    match value {
+        Data::Integer(int) => *int as f64,
+        Data::Float(float) => *float,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An instance of a template with the fields `Course`, `Calories` and `Weight`.
+    fn meal(name: &str, course: Option<&str>, calories: Option<i64>, weight: Option<f64>) -> Template {
+        let mut meal = Template::new("MEAL".to_string())
+            .with_string("Course".to_string(), course.map(str::to_string))
+            .with_integer("Calories".to_string(), calories)
+            .with_float("Weight".to_string(), weight)
+            .build();
+        meal.instance = Some(name.to_string());
+        meal
+    }
+
+    fn query(aggregates: Vec<Aggregate>, groups: &[&str]) -> AggregateQuery {
+        AggregateQuery {
+            template: "MEAL".to_string(),
+            derived: false,
+            condition: None,
+            aggregates,
+            groups: groups.iter().map(|field| field.to_string()).collect(),
+        }
+    }
+
+    fn every(field: &str) -> Vec<Aggregate> {
+        let field = field.to_string();
+        vec![Aggregate::Sum(field.clone()), Aggregate::Avg(field.clone()), Aggregate::Min(field.clone()), Aggregate::Max(field)]
+    }
+
+    #[test]
+    fn orders_groups_by_their_values() {
+        let meals = [
+            meal("Soup", Some("Starter"), None, None),
+            meal("Cake", Some("Dessert"), None, None),
+            meal("Bread", None, None, None),
+            meal("Salad", Some("Starter"), None, None),
+        ];
+        let groups = query(vec![Aggregate::Count], &["Course"]).aggregate(meals.iter().collect()).unwrap();
+        let summary = groups.iter()
+            .map(|group| (group.group.get("Course").cloned(), group.count))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            (Some(Data::String("Dessert".to_string())), Some(1)),
+            (Some(Data::String("Starter".to_string())), Some(2)),
+            (Some(Data::Null), Some(1)),
+        ]);
+    }
+
+    #[test]
+    fn sums_integers_exactly_and_floats_as_floats() {
+        let meals = [meal("Soup", None, Some(120), Some(0.25)), meal("Cake", None, Some(380), Some(0.5)), meal("Bread", None, None, None)];
+        let aggregates = vec![Aggregate::Sum("Calories".to_string()), Aggregate::Sum("Weight".to_string())];
+        let groups = query(aggregates, &[]).aggregate(meals.iter().collect()).unwrap();
+        assert_eq!(groups[0].sum.get("Calories"), Some(&Data::Integer(500)));
+        assert_eq!(groups[0].sum.get("Weight"), Some(&Data::Float(0.75)));
+
+        let meals = [meal("Feast", None, Some(i64::MAX), None), meal("Snack", None, Some(1), None)];
+        let result = query(vec![Aggregate::Sum("Calories".to_string())], &[]).aggregate(meals.iter().collect());
+        assert!(matches!(result, Err(RequestError::TypeMismatch(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn aggregates_of_null_columns_are_null() {
+        let meals = [meal("Soup", None, None, None), meal("Cake", None, None, None)];
+        let groups = query(every("Weight"), &[]).aggregate(meals.iter().collect()).unwrap();
+        for aggregates in [&groups[0].sum, &groups[0].avg, &groups[0].min, &groups[0].max] {
+            assert_eq!(aggregates.get("Weight"), Some(&Data::Null));
+        }
+    }
+
+    #[test]
+    fn aggregates_no_instances_into_a_single_group_without_group_by() {
+        let mut aggregates = every("Calories");
+        aggregates.push(Aggregate::Count);
+        let groups = query(aggregates.clone(), &[]).aggregate(Vec::new()).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!((groups[0].group.len(), groups[0].count), (0, Some(0)));
+        assert_eq!(groups[0].avg.get("Calories"), Some(&Data::Null));
+
+        assert!(query(aggregates, &["Course"]).aggregate(Vec::new()).unwrap().is_empty());
+    }
+}
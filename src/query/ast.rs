@@ -0,0 +1,238 @@
+use time::OffsetDateTime;
+
+use crate::{data::{serialization::DataType, constraint::Constraint}, auth::Grant};
+
+/// A single statement of a request, every statement is terminated by a `;`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    /// `TYPE "DAY"; NAME "First" TYPE STRING; END;`
+    DeclareTemplate(TemplateDecl),
+    /// `CREATE "Monday" TYPE "DAY";` or with a `THEN` block of actions, which set the fields of the new instance.
+    Create {
+        instance: String,
+        template: String,
+        actions: Vec<Action>,
+    },
+    /// `QUERY "Monday" GET "First";` or a `THEN` block of actions.
+    ///
+    /// Queries without any actions return the whole instance.
+    /// If the instance doesn't meet the `WHERE` condition, nothing is returned or changed.
+    Query {
+        instance: String,
+        condition: Option<Condition>,
+        actions: Vec<Action>,
+    },
+    /// `QUERY TYPE;`
+    QueryTemplates,
+    /// `QUERY TYPE "DAY" GET "First";`
+    QueryType(TypeQuery),
+    /// `QUERY TYPE "DAY" COUNT, AVG "Seconds" GROUP BY "First";`
+    QueryAggregate(AggregateQuery),
+    /// `QUERY TYPE "DAY" HISTORY;`
+    QueryHistory(String),
+    /// `ALTER TYPE "DAY" DROP NAME "Third";`, changes the template and every existing instance of it.
+    AlterTemplate {
+        template: String,
+        alteration: Alteration,
+    },
+    /// `DELETE "Monday";`
+    DeleteInstance(String),
+    /// `DELETE TYPE "DAY";`
+    DeleteTemplate(String),
+    /// `GRANT READ ON TYPE "DAY" TO "reporting";`
+    Grant(Grant),
+    /// `REVOKE READ ON TYPE "DAY" FROM "reporting";`
+    Revoke(Grant),
+}
+
+/// The declaration of a new template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateDecl {
+    pub name: String,
+    /// `TYPE "WORKDAY" EXTENDS "DAY";` inherits the fields of the parent template.
+    pub parent: Option<String>,
+    pub fields: Vec<FieldDecl>,
+}
+
+/// `NAME "Day" TYPE INTEGER STARTING 1 MIN 1 MAX 7`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDecl {
+    pub name: String,
+    pub data_type: DataType,
+    pub starting: Option<Expr>,
+    pub constraints: Vec<Constraint>,
+}
+
+/// A change to the fields of an existing template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Alteration {
+    /// `ADD NAME "Fourth" TYPE STRING STARTING "Nothing"`, existing instances get the starting value.
+    Add(FieldDecl),
+    /// `DROP NAME "Fourth"`
+    Drop(String),
+    /// `RENAME NAME "Fourth" TO "Last"`
+    Rename {
+        field: String,
+        to: String,
+    },
+    /// `MODIFY NAME "Seconds" TYPE INTEGER`, existing values are converted to the new type.
+    Modify {
+        field: String,
+        data_type: DataType,
+    },
+}
+
+/// An operation executed on a queried instance.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// `GET "First" "Second"`
+    Get(Vec<String>),
+    /// `SET "First" VALUE "Science"`
+    Set {
+        field: String,
+        value: Expr,
+    },
+    /// `APPEND "Tags" VALUE "urgent"`
+    Append {
+        field: String,
+        value: Expr,
+    },
+    /// `REMOVE "Tags" VALUE "urgent"` removes every item equal to the value.
+    Remove {
+        field: String,
+        value: Expr,
+    },
+}
+
+/// A query over every instance of a template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeQuery {
+    pub template: String,
+    /// `DERIVED` includes the instances of templates derived from it.
+    pub derived: bool,
+    /// Only instances that meet the `WHERE` condition are returned.
+    pub condition: Option<Condition>,
+    /// `ORDER BY "Day" DESC, "First"`, instances are ordered by their name after these.
+    pub order: Vec<SortKey>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+    /// `CURSOR 10 AFTER "token"` returns a page instead of every instance, it replaces `LIMIT` and `OFFSET`.
+    pub cursor: Option<Cursor>,
+    /// The fields of `GET`, every instance is returned whole if there are none.
+    pub fields: Vec<String>,
+}
+
+/// A query that summarizes the instances of a template instead of returning them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateQuery {
+    pub template: String,
+    /// `DERIVED` includes the instances of templates derived from it.
+    pub derived: bool,
+    /// Only instances that meet the `WHERE` condition are aggregated.
+    pub condition: Option<Condition>,
+    pub aggregates: Vec<Aggregate>,
+    /// The fields of `GROUP BY`, every instance is part of a single group if there are none.
+    pub groups: Vec<String>,
+}
+
+/// A summary of the instances of a group.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregate {
+    /// `COUNT`, the number of instances.
+    Count,
+    /// `SUM "Seconds"`, the sum of a number field.
+    Sum(String),
+    /// `AVG "Seconds"`, the average of a number field as float.
+    Avg(String),
+    /// `MIN "Day"`
+    Min(String),
+    /// `MAX "Day"`
+    Max(String),
+}
+
+/// Pages through the instances of a type query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    /// The number of instances per page.
+    pub size: usize,
+    /// The token returned with the previous page, the first page is returned without one.
+    pub after: Option<String>,
+}
+
+/// A field to order instances by, `"Day" DESC`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub field: String,
+    pub descending: bool,
+}
+
+/// A condition an instance has to meet, e.g. `WHERE "Day" >= 3 AND "First" = "Science"`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// `"field" IS NULL` or `"field" IS NOT NULL` when negated.
+    IsNull {
+        field: String,
+        negated: bool,
+    },
+    /// `"Day" >= 3`
+    Compare {
+        field: String,
+        operator: Operator,
+        value: Expr,
+    },
+    /// `"First" LIKE "Sci%"`, `%` matches any number of characters and `_` a single one.
+    Like {
+        field: String,
+        pattern: String,
+    },
+    /// `"Day" IN (1, 2, 3)`
+    In {
+        field: String,
+        values: Vec<Expr>,
+    },
+    Not(Box<Condition>),
+    /// Every condition of a chain of `AND`s, so long chains don't nest.
+    And(Vec<Condition>),
+    /// Every condition of a chain of `OR`s.
+    Or(Vec<Condition>),
+}
+
+/// Comparison operators of a [`Condition`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operator = match self {
+            Operator::Equal => "=",
+            Operator::NotEqual => "!=",
+            Operator::Less => "<",
+            Operator::LessEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
+        };
+        f.write_str(operator)
+    }
+}
+
+/// An expression that evaluates to a value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    /// `TRUE` or `FALSE`
+    Boolean(bool),
+    /// `TIMESTAMP "2022-05-02T08:00:00+02:00"`
+    Timestamp(OffsetDateTime),
+    /// `BYTES "aGVsbG8="`
+    Bytes(Vec<u8>),
+    Null,
+}
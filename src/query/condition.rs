@@ -0,0 +1,209 @@
+use std::cmp::Ordering;
+
+use crate::data::{template::Template, serialization::{Data, DataType}};
+
+use super::{ast::{Condition, Operator, Expr}, error::RequestError, evaluate};
+
+impl Condition {
+    /// Makes sure that every field of the condition is part of the template
+    /// and that every value can be compared to the field.
+    pub fn check(&self, template: &Template, templates: &[Template]) -> Result<(), RequestError> {
+        let field_type = |field: &String| template.field_type(field, templates)
+            .ok_or_else(|| RequestError::FieldNonExistent(field.clone()));
+        match self {
+            Condition::IsNull { field, .. } => field_type(field).map(|_| ()),
+            Condition::Compare { field, value, .. } => comparable(field, field_type(field)?, value),
+            Condition::Like { field, .. } => match field_type(field)? {
+                DataType::STRING => Ok(()),
+                data_type => Err(RequestError::TypeMismatch(format!(
+                    "LIKE only applies to strings, field \"{}\" is of type {}.", field, data_type,
+                ))),
+            },
+            Condition::In { field, values } => {
+                let data_type = field_type(field)?;
+                values.iter().try_for_each(|value| comparable(field, data_type, value))
+            },
+            Condition::Not(condition) => condition.check(template, templates),
+            Condition::And(conditions) | Condition::Or(conditions) => conditions.iter()
+                .try_for_each(|condition| condition.check(template, templates)),
+        }
+    }
+
+    /// Returns true if the instance meets the condition.
+    pub fn test(&self, instance: &Template) -> bool {
+        self.truth(instance) == Some(true)
+    }
+
+    /// Evaluates the condition with three-valued logic, `None` means unknown.
+    ///
+    /// Comparing a field that is null or missing is unknown, and so is its negation,
+    /// so `NOT "First" = "Math"` doesn't match instances without a value for `First` either.
+    fn truth(&self, instance: &Template) -> Option<bool> {
+        let value = |field: &String| instance.get(field).unwrap_or(&Data::Null);
+        match self {
+            Condition::IsNull { field, negated } => Some((*value(field) == Data::Null) != *negated),
+            Condition::Compare { field, operator, value: expected } => {
+                let ordering = value(field).compare(&evaluate(expected.clone()))?;
+                Some(matches!(
+                    (operator, ordering),
+                    (Operator::Equal, Ordering::Equal)
+                    | (Operator::NotEqual, Ordering::Less | Ordering::Greater)
+                    | (Operator::Less, Ordering::Less)
+                    | (Operator::LessEqual, Ordering::Less | Ordering::Equal)
+                    | (Operator::Greater, Ordering::Greater)
+                    | (Operator::GreaterEqual, Ordering::Greater | Ordering::Equal)
+                ))
+            },
+            Condition::Like { field, pattern } => match value(field) {
+                Data::String(string) => Some(like(string, pattern)),
+                _ => None,
+            },
+            Condition::In { field, values } => match value(field) {
+                Data::Null => None,
+                value => Some(values.iter()
+                    .any(|expected| value.compare(&evaluate(expected.clone())) == Some(Ordering::Equal))),
+            },
+            Condition::Not(condition) => condition.truth(instance).map(|truth| !truth),
+            Condition::And(conditions) => kleene(conditions, instance, false),
+            Condition::Or(conditions) => kleene(conditions, instance, true),
+        }
+    }
+}
+
+/// Joins the conditions with `AND` or `OR`, where `decisive` is false and true respectively.
+///
+/// A single decisive condition decides the result even if others are unknown,
+/// otherwise any unknown condition makes the result unknown.
+fn kleene(conditions: &[Condition], instance: &Template, decisive: bool) -> Option<bool> {
+    let mut result = Some(!decisive);
+    for condition in conditions {
+        match condition.truth(instance) {
+            Some(truth) if truth == decisive => return Some(decisive),
+            Some(_) => {},
+            None => result = None,
+        }
+    }
+    result
+}
+
+/// Fails if the value can't be compared to values of the field, numbers of both types can be compared to each other.
+fn comparable(field: &str, data_type: &DataType, value: &Expr) -> Result<(), RequestError> {
+    let valid = match (data_type, value) {
+        (_, Expr::Null) => return Err(RequestError::TypeMismatch(format!(
+            "Field \"{}\" can't be compared to NULL, use IS NULL instead.", field,
+        ))),
+        (DataType::INTEGER | DataType::FLOAT, Expr::Integer(_) | Expr::Float(_))
+        | (DataType::STRING, Expr::String(_))
+        | (DataType::BOOLEAN, Expr::Boolean(_))
+        | (DataType::TIMESTAMP, Expr::Timestamp(_))
+        | (DataType::BYTES, Expr::Bytes(_)) => true,
+        _ => false,
+    };
+    if !valid {
+        return Err(RequestError::TypeMismatch(format!(
+            "Field \"{}\" of type {} can't be compared to {}.", field, data_type, evaluate(value.clone()).kind(),
+        )));
+    }
+    Ok(())
+}
+
+/// Matches the whole string against a pattern, in which `%` matches any number of characters and `_` a single one.
+fn like(string: &str, pattern: &str) -> bool {
+    let string = string.chars().collect::<Vec<char>>();
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let (mut s, mut p) = (0, 0);
+    // Position of the last `%` and of the character it matches up to, to retry with one more character
+    let mut retry: Option<(usize, usize)> = None;
+    while s < string.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                retry = Some((p, s));
+                p += 1;
+            },
+            Some(c) if *c == '_' || *c == string[s] => {
+                s += 1;
+                p += 1;
+            },
+            _ => match retry {
+                Some((percent, matched)) => {
+                    retry = Some((percent, matched + 1));
+                    p = percent + 1;
+                    s = matched + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '%')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(first: Option<&str>) -> Template {
+        Template::new("DAY".to_string())
+            .with_string("First".to_string(), first.map(str::to_string))
+            .with_integer("Day".to_string(), Some(1))
+            .build()
+    }
+
+    fn first_is(value: &str) -> Condition {
+        Condition::Compare { field: "First".to_string(), operator: Operator::Equal, value: Expr::String(value.to_string()) }
+    }
+
+    fn not(condition: Condition) -> Condition {
+        Condition::Not(Box::new(condition))
+    }
+
+    #[test]
+    fn negated_conditions_on_null_fields_are_unknown() {
+        let null = day(None);
+        assert_eq!(first_is("Math").truth(&null), None);
+        assert!(!not(first_is("Math")).test(&null));
+        assert!(!not(not(first_is("Math"))).test(&null));
+        let like = Condition::Like { field: "First".to_string(), pattern: "Sci%".to_string() };
+        assert!(!not(like.clone()).test(&null));
+        assert!(not(like).test(&day(Some("Math"))));
+        let within = Condition::In { field: "First".to_string(), values: vec![Expr::String("Math".to_string())] };
+        assert!(!not(within).test(&null));
+        assert!(not(Condition::IsNull { field: "First".to_string(), negated: false }).test(&day(Some("Math"))));
+    }
+
+    #[test]
+    fn joins_unknown_conditions() {
+        let null = day(None);
+        let day_is = |day: i64| Condition::Compare { field: "Day".to_string(), operator: Operator::Equal, value: Expr::Integer(day) };
+        assert_eq!(Condition::And(vec![first_is("Math"), day_is(2)]).truth(&null), Some(false));
+        assert_eq!(Condition::And(vec![first_is("Math"), day_is(1)]).truth(&null), None);
+        assert_eq!(Condition::Or(vec![first_is("Math"), day_is(1)]).truth(&null), Some(true));
+        assert_eq!(Condition::Or(vec![first_is("Math"), day_is(2)]).truth(&null), None);
+        assert!(not(Condition::And(vec![first_is("Math"), day_is(2)])).test(&null));
+        assert!(!not(Condition::Or(vec![first_is("Math"), day_is(2)])).test(&null));
+    }
+
+    #[test]
+    fn like_matches_single_characters_and_runs() {
+        assert!(like("Science", "Sci%"));
+        assert!(like("Science", "%ence"));
+        assert!(like("Science", "S_ience"));
+        assert!(like("", "%"));
+        assert!(like("日本語", "_本_"));
+        assert!(!like("Science", "Sci"));
+        assert!(!like("Sci", "Sci_"));
+        assert!(!like("", "_"));
+    }
+
+    #[test]
+    fn like_backtracks_after_percent() {
+        // The first `%` has to give back characters it matched too eagerly
+        assert!(like("abcabcabd", "%abd"));
+        assert!(like("mississippi", "m%iss%ppi"));
+        assert!(like("aXbXc", "%X_"));
+        assert!(like("abab", "%a_"));
+        assert!(like("aaa", "%_%_%_%"));
+        assert!(!like("aa", "%_%_%_%"));
+        assert!(!like("abcabcabc", "%abd"));
+        assert!(!like("mississippi", "m%iss%ppx"));
+    }
+}
@@ -1,18 +1,82 @@
+use serde::Serialize;
 
 /// Possible errors in requests.
 #[derive(Debug)]
 pub enum RequestError {
     TemplateNonExistent,
     TemplateAlreadyExists,
+    /// The template is embedded or extended by another template, holds the name of the other template.
+    TemplateInUse(String),
     InstanceNonExistent,
     InstanceAlreadyExists,
-    SyntaxError,
+    /// A field that isn't part of the instance, holds the name of the field.
+    FieldNonExistent(String),
+    /// A field with the name is already part of the template, holds the name of the field.
+    FieldAlreadyExists(String),
+    /// A field of a derived template that can only be altered on the template it is inherited from.
+    FieldInherited {
+        field: String,
+        /// Name of the template the field is inherited from.
+        template: String,
+    },
+    /// A value doesn't fit the type of its field, holds a description of the mismatch.
+    TypeMismatch(String),
+    /// A value violates a constraint of its field.
+    ConstraintViolation {
+        /// Path of the field.
+        field: String,
+        /// The violated constraint as declared.
+        rule: String,
+    },
+    SyntaxError(SyntaxError),
+    /// A literal that can't be represented by its type, holds the literal as written in the source.
+    InvalidLiteral(String),
+    /// A cursor that wasn't returned by the same query, holds the cursor as written in the source.
+    InvalidCursor(String),
+    /// The decrypted request isn't valid UTF-8.
+    InvalidEncoding,
+    /// The request couldn't be decrypted, either it was tampered with or encrypted using another key.
+    DecryptionFailed,
+    /// The client failed to prove that it knows the secret of the user it claims to be.
+    AuthenticationFailed,
+    /// The user lacks a permission on a template, holds a description of what was denied.
+    PermissionDenied(String),
     SerializationError
 }
 
+/// Describes where the parser failed to understand the source.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyntaxError {
+    /// The offending token as written in the source, `None` at the end of the source.
+    pub token: Option<String>,
+    /// The tokens of the statement right before the offending token.
+    #[serde(skip)]
+    pub after: Option<String>,
+    pub expected: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected {}", self.expected)?;
+        if let Some(after) = &self.after {
+            write!(f, " after {}", after)?;
+        }
+        write!(f, " at {}:{}", self.line, self.column)?;
+        match &self.token {
+            Some(token) => write!(f, ", found {}", token),
+            None => write!(f, ", found end of source"),
+        }
+    }
+}
+
 impl std::fmt::Display for RequestError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            RequestError::SyntaxError(err) => write!(f, "SyntaxError: {}", err),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
@@ -24,4 +88,4 @@ impl std::error::Error for RequestError {
     fn cause(&self) -> Option<&dyn std::error::Error> {
         self.source()
     }
-}
\ No newline at end of file
+}
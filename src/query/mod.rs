@@ -1,356 +1,1098 @@
-use std::sync::MutexGuard;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 
-use crate::data::{template::Template, TEMPLATES, INSTANCES, serialization::Data};
-use error::RequestError;
 use linked_hash_map::LinkedHashMap;
-use crate::lexer::data::{Token, TokenMatch};
+use serde::Serialize;
+
+use crate::data::{template::Template, TEMPLATES, INSTANCES, GRANTS, lock, serialization::{Data, DataType}};
+use crate::data::constraint::Constraint;
+use crate::data::history::{Change, Revision, rename};
+use crate::auth::{ADMIN_ROLE, Grant, Permission};
+use crate::lexer::data::TokenMatch;
+use ast::{Statement, TemplateDecl, Action, Expr, Condition, Alteration, TypeQuery, AggregateQuery};
+use error::RequestError;
+use page::{Page, Position};
+use aggregate::Aggregation;
 
+pub mod ast;
 pub mod error;
+pub mod parser;
+mod condition;
+mod aggregate;
+mod page;
 
-/// Creates a new [`Template`] from a template declaration block.
-pub fn create_template(mut lines: Vec<Vec<TokenMatch>>) -> Result<Template, RequestError> {
-    let first = lines.remove(0);
-    lines.remove(lines.len()-1);
-    // Validate statement begin
-    if first.len() != 2 { return Err(RequestError::SyntaxError); }
-    // Get name of template
-    let name = first.get(1).unwrap();
-    let mut template = Template::new(name.value.clone());
-    // Loop over field declaration lines
-    for line in lines {
-        // if the line only has 4 tokens then it has no starting value
-        if line.len() == 4 {
-            let field = line.get(1).unwrap();
-            let data_type = line.get(3).unwrap();
-            template = match data_type.token {
-                Token::StringType => {
-                    template.with_string(field.value.clone(), Some("".to_owned()))
-                },
-                Token::IntegerType => {
-                    template.with_integer(field.value.clone(), Some(0))
-                },
-                Token::FloatType => {
-                    template.with_float(field.value.clone(), Some(0.0))
-                },
-                _ => { return Err(RequestError::SyntaxError); }
-            }
-        // if it has 6 tokens it has a starting value
-        } else if line.len() == 6 {
-            let field = line.get(1).unwrap();
-            let data_type = line.get(3).unwrap();
-            let starting = line.get(5).unwrap();
-            template = match data_type.token {
-                Token::StringType => {
-                    template.with_string(field.value.clone(), Some(starting.value.clone()))
-                },
-                Token::IntegerType => {
-                    template.with_integer(field.value.clone(), Some(starting.value.clone().parse::<i64>().unwrap()))
-                },
-                Token::FloatType => {
-                    template.with_float(field.value.clone(), Some(starting.value.clone().parse::<f64>().unwrap()))
-                },
-                _ => { return Err(RequestError::SyntaxError); }
-            }
-        // throw an error at any other size
-        } else {
-            return Err(RequestError::SyntaxError)
+/// Information about who is executing the statements.
+pub struct Context {
+    /// Name of the authenticated user.
+    pub user: String,
+    /// Roles of the user, permissions are granted to roles.
+    pub roles: Vec<String>,
+}
+
+impl Context {
+    fn is_admin(&self) -> bool {
+        self.roles.iter().any(|role| role == ADMIN_ROLE)
+    }
+
+    /// Returns true if the user is an admin or one of its roles has been granted the permission on the template.
+    fn is_allowed(&self, permission: Permission, template: &str) -> bool {
+        self.is_admin() || lock(&GRANTS).iter().any(|grant| {
+            grant.permission == permission && grant.template == template && self.roles.contains(&grant.role)
+        })
+    }
+
+    /// Returns true if the user is an admin or one of its roles has been granted any permission on the template.
+    fn knows(&self, template: &str) -> bool {
+        self.is_admin() || lock(&GRANTS).iter().any(|grant| grant.template == template && self.roles.contains(&grant.role))
+    }
+
+    fn authorize(&self, permission: Permission, template: &str) -> Result<(), RequestError> {
+        if self.is_allowed(permission, template) {
+            return Ok(());
+        }
+        Err(RequestError::PermissionDenied(format!(
+            "User \"{}\" lacks the {} permission on type \"{}\".", self.user, permission, template,
+        )))
+    }
+
+    fn authorize_admin(&self) -> Result<(), RequestError> {
+        if self.is_admin() {
+            return Ok(());
+        }
+        Err(RequestError::PermissionDenied(format!(
+            "Only users with the \"{}\" role are allowed to grant and revoke permissions, \"{}\" doesn't have it.",
+            ADMIN_ROLE, self.user,
+        )))
+    }
+}
+
+/// Creates a new [`Template`] from a template declaration.
+///
+/// Embedded templates and the parent template have to be part of the given templates.
+pub fn create_template(decl: TemplateDecl, templates: &[Template]) -> Result<Template, RequestError> {
+    let mut template = Template::new(decl.name);
+    if let Some(parent) = decl.parent {
+        let parent = templates.iter()
+            .find(|t| t.template.as_ref() == Some(&parent))
+            .ok_or(RequestError::TemplateNonExistent)?;
+        template = template.extends(parent);
+    }
+    for field in decl.fields {
+        if template.has_field(&field.name) {
+            return Err(RequestError::FieldAlreadyExists(field.name));
+        }
+        template = template.with_constraints(field.name.clone(), field.constraints);
+        // The parser makes sure that starting values fit the type of the field
+        template = match (field.data_type, field.starting) {
+            (data_type, Some(value)) => template.with_data(field.name, data_type, evaluate(value)),
+            (DataType::STRING, None) => template.with_string(field.name, None),
+            (DataType::INTEGER, None) => template.with_integer(field.name, None),
+            (DataType::FLOAT, None) => template.with_float(field.name, None),
+            (DataType::BOOLEAN, None) => template.with_boolean(field.name, None),
+            (DataType::TIMESTAMP, None) => template.with_timestamp(field.name, None),
+            (DataType::BYTES, None) => template.with_bytes(field.name, None),
+            (DataType::LIST(element), None) => template.with_list(field.name, *element),
+            (DataType::TEMPLATE(name), None) => {
+                let embedded = templates.iter()
+                    .find(|t| t.template.as_ref() == Some(&name))
+                    .ok_or(RequestError::TemplateNonExistent)?;
+                template.with_template(field.name, embedded)
+            },
         }
     }
     Ok(template.build())
 }
 
-/// executes multiline queries.
-pub fn multiline_query(instance: Template, lines: Vec<Vec<TokenMatch>>, mutex: &mut MutexGuard<Vec<Template>>) -> Result<Vec<Template>, RequestError> {
-    let mut output: Vec<Template> = Vec::new();
-    let mut instance = Box::new(instance);
-    for line in lines {
-        let mut iter = line.iter();
-        match iter.next() {
-            Some(next) => match next.token {
-                Token::Get => match iter.next() {
-                    Some(next) => {
-                        let mut instance_clone = instance.clone(); 
-                        let instance_data = instance_clone.data.clone();
-                        instance_clone.data = LinkedHashMap::new();
-                        let field = next.value.clone();
-                        let data = instance_data.get(&field).unwrap().clone();
-                        let mut map: LinkedHashMap<String, Data> = LinkedHashMap::new();
-                        map.insert(field, data);
-                        instance_clone.data.extend(map.clone());
-                        'inner: loop {
-                            match iter.next() {
-                                Some(next) => match next.token {
-                                    Token::Literal => {
-                                        let field = next.value.clone();
-                                        let data = instance_data.get(&field).unwrap().clone();
-                                        map.insert(field, data);
-                                        instance_clone.data.extend(map.clone());
-                                    }
-                                    _ => return Err(RequestError::SyntaxError)
-                                },
-                                None => break 'inner,
-                            }
-                        }
-                        output.push(*instance_clone);
-                    },
-                    None => return Err(RequestError::SyntaxError),
-                }
-                Token::Set => {
-                    match iter.next() {
-                        Some(next) => match next.token {
-                            Token::Literal => {
-                                let key = next.value.clone();
-                                match iter.next() {
-                                    Some(next) => match next.token {
-                                        Token::Value => match iter.next() {
-                                            Some(next) => match next.token {
-                                                Token::Literal => {
-                                                    let value = next.value.clone();
-                                                    instance.data.insert(key, Data::from(value));
-                                                },
-                                                Token::Integer => {
-                                                    let value = next.value.clone().parse::<i64>().unwrap();
-                                                    instance.data.insert(key, Data::from(value));
-                                                },
-                                                Token::Float => {
-                                                    let value = next.value.clone().parse::<f64>().unwrap();
-                                                    instance.data.insert(key, Data::from(value));
-                                                },
-                                                _ => return Err(RequestError::SyntaxError)
-                                            },
-                                            None => return Err(RequestError::SyntaxError),
-                                        }
-                                        _ => return Err(RequestError::SyntaxError)
-                                    },
-                                    None => return Err(RequestError::SyntaxError),
-                                }
-                            }
-                            _ => return Err(RequestError::SyntaxError)
-                        },
-                        None => return Err(RequestError::SyntaxError),
-                    }
+/// Evaluates an [`Expr`] to the [`Data`] it represents.
+fn evaluate(expr: Expr) -> Data {
+    match expr {
+        Expr::String(value) => Data::from(value),
+        Expr::Integer(value) => Data::from(value),
+        Expr::Float(value) => Data::from(value),
+        Expr::Boolean(value) => Data::from(value),
+        Expr::Timestamp(value) => Data::from(value),
+        Expr::Bytes(value) => Data::from(value),
+        Expr::Null => Data::Null,
+    }
+}
+
+/// Returns a copy of the instance that only contains the given fields.
+///
+/// Fields of embedded templates are named by their dotted path.
+fn project(instance: &Template, fields: &[String]) -> Result<Template, RequestError> {
+    let mut data = LinkedHashMap::new();
+    for field in fields {
+        let value = instance.get(field)
+            .ok_or_else(|| RequestError::FieldNonExistent(field.clone()))?;
+        data.insert(field.clone(), value.clone());
+    }
+    Ok(Template { data, ..instance.clone() })
+}
 
+/// Declares a new template, the user has to be allowed to read the templates it extends or embeds.
+fn declare_template(decl: TemplateDecl, context: &Context) -> Result<(), RequestError> {
+    context.authorize(Permission::Create, &decl.name)?;
+    if let Some(parent) = &decl.parent {
+        context.authorize(Permission::Read, parent)?;
+    }
+    for field in decl.fields.iter() {
+        authorize_embedding(&field.data_type, context)?;
+    }
+    let mut mutex = lock(&TEMPLATES);
+    let template = create_template(decl, &mutex)?;
+    if mutex.iter().any(|t| t.template == template.template) {
+        return Err(RequestError::TemplateAlreadyExists);
+    }
+    mutex.push(template);
+    Ok(())
+}
+
+/// Embedding a template reveals its fields and starting values, so the user has to be allowed to read it.
+fn authorize_embedding(data_type: &DataType, context: &Context) -> Result<(), RequestError> {
+    match data_type {
+        DataType::TEMPLATE(embedded) => context.authorize(Permission::Read, embedded),
+        _ => Ok(()),
+    }
+}
+
+/// Creates an instance of the template, the actions set its fields before the constraints are checked.
+fn create_instance(name: String, template_name: String, actions: Vec<Action>, context: &Context) -> Result<(), RequestError> {
+    context.authorize(Permission::Create, &template_name)?;
+    let templates = lock(&TEMPLATES);
+    let template = templates.iter()
+        .find(|template| template.template.as_ref() == Some(&template_name))
+        .ok_or(RequestError::TemplateNonExistent)?;
+    let mut instances = lock(&INSTANCES);
+    if instances.iter().any(|i| i.instance.as_ref() == Some(&name)) {
+        return Err(RequestError::InstanceAlreadyExists);
+    }
+
+    // Instances use the schema, constraints, parent and history of their template
+    let mut instance = Template {
+        instance: Some(name),
+        schema: LinkedHashMap::new(),
+        constraints: LinkedHashMap::new(),
+        extends: None,
+        history: Vec::new(),
+        ..template.clone()
+    };
+    apply(&mut instance, actions, template, &templates)?;
+    let unique = UniqueValues::new(template, &templates, instances.iter().chain([&instance]));
+    validate(&instance.data, template, "", &templates, &unique)?;
+    instances.push(instance);
+    Ok(())
+}
+
+/// Executes the actions on a single instance, if it meets the condition.
+///
+/// The actions are applied to a copy, which only replaces the stored instance if every action succeeded.
+/// Changed values have to fit the types of the template.
+fn query_instance(name: String, condition: Option<Condition>, actions: Vec<Action>, context: &Context) -> Result<Vec<Template>, RequestError> {
+    let templates = lock(&TEMPLATES);
+    let mut mutex = lock(&INSTANCES);
+    let index = find_instance(&mutex, &name, context)?;
+    let template = templates.iter()
+        .find(|t| t.template == mutex[index].template)
+        .ok_or(RequestError::TemplateNonExistent)?;
+
+    let template_name = template.template.as_deref().unwrap_or_default();
+    if actions.is_empty() || condition.is_some() || actions.iter().any(|action| matches!(action, Action::Get(_))) {
+        context.authorize(Permission::Read, template_name)?;
+    }
+    if actions.iter().any(|action| !matches!(action, Action::Get(_))) {
+        context.authorize(Permission::Write, template_name)?;
+    }
+
+    if let Some(condition) = condition {
+        condition.check(template, &templates)?;
+        if !condition.test(&mutex[index]) {
+            return Ok(Vec::new());
+        }
+    }
+
+    if actions.is_empty() {
+        return Ok(vec![mutex[index].clone()]);
+    }
+
+    let mut instance = mutex[index].clone();
+    let output = apply(&mut instance, actions, template, &templates)?;
+    if instance != mutex[index] {
+        let changed = mutex.iter()
+            .enumerate()
+            .map(|(i, other)| if i == index { &instance } else { other });
+        let unique = UniqueValues::new(template, &templates, changed);
+        validate(&instance.data, template, "", &templates, &unique)?;
+        mutex[index] = instance;
+    }
+    Ok(output)
+}
+
+/// Applies the actions to the instance, changed values have to fit the types of the template.
+///
+/// Returns the output of the GET actions.
+fn apply(instance: &mut Template, actions: Vec<Action>, template: &Template, templates: &[Template]) -> Result<Vec<Template>, RequestError> {
+    let mut output = Vec::new();
+    for action in actions {
+        match action {
+            Action::Get(fields) => output.push(project(instance, &fields)?),
+            Action::Set { field, value } => {
+                let data_type = template.field_type(&field, templates)
+                    .ok_or_else(|| RequestError::FieldNonExistent(field.clone()))?;
+                // Only list items are reached by an index
+                let value = match field.ends_with(']') {
+                    true => coerce_item(evaluate(value), data_type, || format!("List \"{}\"", field))?,
+                    false => coerce(evaluate(value), data_type, || format!("Field \"{}\"", field))?,
+                };
+                embed(instance, template, templates, &field);
+                if !instance.set(&field, value) {
+                    return Err(RequestError::FieldNonExistent(field));
                 }
-                _ => return Err(RequestError::SyntaxError)
             },
-            None => return Err(RequestError::SyntaxError),
+            Action::Append { field, value } => {
+                let value = coerce_item(evaluate(value), item_type(template, templates, &field)?, || format!("List \"{}\"", field))?;
+                list(instance, &field)?.push(value);
+            },
+            Action::Remove { field, value } => {
+                let value = coerce_item(evaluate(value), item_type(template, templates, &field)?, || format!("List \"{}\"", field))?;
+                list(instance, &field)?.retain(|item| *item != value);
+            },
         }
     }
-    mutex.push(*instance);
     Ok(output)
 }
 
-// TODO: Should be reworked to feature an ast with dynamic execution. For now this very rigid model works `fine`.
-/// Executes the statements from the query.
-pub fn execute_statements(mut lines: Vec<Vec<TokenMatch>>) -> Result<Vec<Template>, RequestError> {
-    let mut output: Vec<Template> = Vec::new();
-    for (index, line) in lines.clone().iter().enumerate() {
-        let mut iter = line.iter();
-        match iter.next() {
-            Some(next) => match next.token {
-                Token::Create => match iter.next() {
-                    Some(next) => match next.token {
-                        Token::Literal => {
-                            // name of the instance
-                            let name = next.value.clone();
-                            match iter.next() {
-                                Some(next) => match next.token {
-                                    Token::Type => match iter.next() {
-                                        Some(next) => {
-                                            // name of the template 
-                                            let template_name = next.value.clone();
-                                            // grab template and make instance
-                                            let mutex = TEMPLATES.lock().unwrap();
-                                            let mut instance = mutex.iter()
-                                                .filter(
-                                                    |template| 
-                                                    template.template.as_ref().unwrap().clone() == template_name
-                                                ).collect::<Vec<&Template>>().get(0).unwrap().clone().clone();
-                                            instance.instance = Some(name);
-                                            let mut mutex = INSTANCES.lock().unwrap();
-                                            mutex.push(instance);
-                                            lines.remove(0);
-                                        },
-                                        None => return Err(RequestError::SyntaxError),
-                                    },
-                                    _ => return Err(RequestError::SyntaxError),
-                                },
-                                None => return Err(RequestError::SyntaxError),
-                            }
-                        },
-                        _ => return Err(RequestError::SyntaxError)
-                    },
-                    None => return Err(RequestError::SyntaxError),
-                },
-                Token::Query => {
-                    match iter.next() {
-                        Some(next) => {
-                            match next.token {
-                                Token::Type => {
-                                    let mutex = TEMPLATES.lock().unwrap();
-                                    output.extend(mutex.clone())
-                                },
-                                Token::Literal => {
-                                    let mut mutex = INSTANCES.lock().unwrap();
-                                    let index = *mutex.iter().enumerate()
-                                        .filter(|(_, template)| template.instance == Some(next.value.clone()))
-                                        .map(|(index, _)| index).collect::<Vec<usize>>().get(0).unwrap(); // TODO: Throw error
-                                    let mut instance = mutex.remove(index);
-                                    match iter.next() {
-                                        Some(next) => match next.token {
-                                            
-                                            Token::Get => {
-                                                let data = instance.data.clone();
-                                                instance.data = LinkedHashMap::new();
-                                                loop {
-                                                    match iter.next() {
-                                                        Some(next) => match next.token {
-                                                            Token::Literal => {
-                                                                let field = next.value.clone();
-                                                                let data = data.get(&field).unwrap().clone();
-                                                                let mut map: LinkedHashMap<String, Data> = LinkedHashMap::new();
-                                                                map.insert(field, data);
-                                                                instance.data.extend(map);
-                                                                //lines.remove(0);
-                                                            }
-                                                            _ => return Err(RequestError::SyntaxError)
-                                                        },
-                                                        None => break,
-                                                    }
-                                                }
-                                                output.push(instance.clone());
-                                                mutex.push(instance);
-                                            }
-                                            Token::Set => {
-                                                match iter.next() {
-                                                    Some(next) => match next.token {
-                                                        Token::Literal => {
-                                                            let key = next.value.clone();
-                                                            match iter.next() {
-                                                                Some(next) => match next.token {
-                                                                    Token::Value => match iter.next() {
-                                                                        Some(next) => match next.token {
-                                                                            Token::Literal => {
-                                                                                let value = next.value.clone();
-                                                                                instance.data.insert(key, Data::from(value));
-                                                                                mutex.push(instance)
-                                                                            },
-                                                                            Token::Integer => {
-                                                                                let value = next.value.clone().parse::<i64>().unwrap();
-                                                                                instance.data.insert(key, Data::from(value));
-                                                                                mutex.push(instance)
-                                                                            },
-                                                                            Token::Float => {
-                                                                                let value = next.value.clone().parse::<f64>().unwrap();
-                                                                                instance.data.insert(key, Data::from(value));
-                                                                                mutex.push(instance)
-                                                                            },
-                                                                            _ => return Err(RequestError::SyntaxError)
-                                                                        },
-                                                                        None => return Err(RequestError::SyntaxError),
-                                                                    }
-                                                                    _ => return Err(RequestError::SyntaxError)
-                                                                },
-                                                                None => return Err(RequestError::SyntaxError),
-                                                            }
-                                                        }
-                                                        _ => return Err(RequestError::SyntaxError)
-                                                    },
-                                                    None => return Err(RequestError::SyntaxError),
-                                                }
-                                            }
-                                            Token::Then => {
-                                                let start_index = index+1;
-                                                let end_index = *lines.iter().enumerate()
-                                                    .filter(|(_, line)| line.get(0).unwrap().token == Token::End)
-                                                    .map(|(index, _)| index).collect::<Vec<usize>>().get(0).unwrap()-1;
-                                                let set_lines: Vec<Vec<TokenMatch>> = lines.drain(start_index..=end_index).collect();
-                                                lines.drain(0..end_index);
-                                                let result = multiline_query(instance.clone(), set_lines, &mut mutex)?;
-                                                output.extend(result.clone());
-                                            }
-                                            _ => return Err(RequestError::SyntaxError)
-                                        },
-                                        None => {
-                                            output.push(instance.clone());
-                                            mutex.push(instance);
-                                        },
-                                    }
-                                },
-                                _ => return Err(RequestError::SyntaxError)
-                            }
-                        },
-                        None => return Err(RequestError::SyntaxError),
-                    }
-                },
-                // Pull out the whole template
-                Token::Type => {
-                    //let start_index = index;
-                    let start_index = 0;
-                    let end_index = *lines.iter().enumerate()
-                        .filter(|(_, line)| line.get(0).unwrap().token == Token::End)
-                        .map(|(index, _)| index).collect::<Vec<usize>>().get(0).unwrap();
-                    let template: Vec<Vec<TokenMatch>> = lines.drain(start_index..=end_index).collect();
-                    let template = create_template(template)?;
-                    let mut mutex = TEMPLATES.lock().unwrap();
-                    if mutex.contains(&template) {
-                        return Err(RequestError::InstanceAlreadyExists);
-                    }
-                    mutex.push(template.clone());
-                },
-                // Deletion
-                Token::Delete => match iter.next() {
-                    Some(next) => match next.token {
-                        Token::Literal => {
-                            let mut mutex = INSTANCES.lock().unwrap();
-                            let index = *mutex.iter().enumerate()
-                                .filter(|(_, template)| template.instance == Some(next.value.clone()))
-                                .map(|(index, _)| index).collect::<Vec<usize>>().get(0).unwrap(); // TODO: Throw error
-                            mutex.remove(index);
-                        },
-                        Token::Type => match iter.next() {
-                            Some(next) => match next.token {
-                                Token::Literal => {
-                                    let mut mutex = TEMPLATES.lock().unwrap();
-                                    let index = *mutex.iter().enumerate()
-                                        .filter(|(_, template)| template.template == Some(next.value.clone()))
-                                        .map(|(index, _)| index).collect::<Vec<usize>>().get(0).unwrap(); // TODO: Throw error
-                                    mutex.remove(index);
-                                    // Remove Instances
-                                    let mut mutex = INSTANCES.lock().unwrap();
-                                    for (index, instance) in mutex.clone().iter().enumerate() {
-                                        if instance.template.as_ref().unwrap_or(&"".to_owned()) == &next.value {
-                                            mutex.remove(index);
-                                        }
-                                    }
-                                },
-                                _ => return Err(RequestError::SyntaxError),
-                            },
-                            None => return Err(RequestError::SyntaxError),
-                        }
-                        _ => return Err(RequestError::SyntaxError), 
-                    },
-                    None => return Err(RequestError::SyntaxError),
-                },
-                // Ignore declaration Tokens
-                Token::Name => {},
-                Token::End => {},
-                // Ignore multiline query set
-                Token::Set => {}
-                Token::Get => {}
-                _ => return Err(RequestError::SyntaxError)
+/// Checks the constraints of the template and its embedded templates against the fields of an instance.
+///
+/// `unique` holds the values of every instance of the template, including the checked one.
+fn validate(
+    fields: &LinkedHashMap<String, Data>,
+    template: &Template,
+    prefix: &str,
+    templates: &[Template],
+    unique: &UniqueValues,
+) -> Result<(), RequestError> {
+    for (name, data_type) in template.schema.iter() {
+        let path = match prefix.is_empty() {
+            true => name.clone(),
+            false => format!("{}.{}", prefix, name),
+        };
+        let value = match fields.get(name) {
+            Some(value) => value,
+            None => continue,
+        };
+        for constraint in template.constraints.get(name).into_iter().flatten() {
+            let valid = match constraint {
+                Constraint::Required => *value != Data::Null,
+                // Null values never collide
+                Constraint::Unique => *value == Data::Null || unique.count(&path, value) <= 1,
+                _ => constraint.allows(value),
+            };
+            if !valid {
+                return Err(RequestError::ConstraintViolation { field: path, rule: constraint.to_string() });
+            }
+        }
+        if let (DataType::TEMPLATE(embedded), Data::Object(nested)) = (data_type, value) {
+            if let Some(embedded) = templates.iter().find(|t| t.template.as_ref() == Some(embedded)) {
+                validate(nested, embedded, &path, templates, unique)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The values the instances of a template have in its `UNIQUE` fields, by the path of the field.
+///
+/// Each field's values are sorted by [`Data::total_cmp`], so counting a value doesn't compare it against every instance.
+struct UniqueValues<'a>(HashMap<String, Vec<&'a Data>>);
+
+impl<'a> UniqueValues<'a> {
+    /// Collects the values of the instances of the template, nothing is collected if no field is unique.
+    fn new(template: &Template, templates: &[Template], instances: impl Iterator<Item = &'a Template>) -> Self {
+        let mut values = unique_paths(template, templates, "").into_iter()
+            .map(|path| (path, Vec::new()))
+            .collect::<HashMap<String, Vec<&Data>>>();
+        if values.is_empty() {
+            return UniqueValues(values);
+        }
+        for instance in instances.filter(|i| i.template == template.template) {
+            for (path, collected) in values.iter_mut() {
+                // Null values never collide
+                if let Some(value) = instance.get(path).filter(|value| **value != Data::Null) {
+                    collected.push(value);
+                }
+            }
+        }
+        for collected in values.values_mut() {
+            collected.sort_by(|a, b| a.total_cmp(b));
+        }
+        UniqueValues(values)
+    }
+
+    /// Number of instances with the value in the field at the path.
+    fn count(&self, path: &str, value: &Data) -> usize {
+        let collected = match self.0.get(path) {
+            Some(collected) => collected,
+            None => return 0,
+        };
+        let start = collected.partition_point(|other| other.total_cmp(value).is_lt());
+        let end = collected.partition_point(|other| other.total_cmp(value).is_le());
+        end - start
+    }
+}
+
+/// Paths of the `UNIQUE` fields of the template and its embedded templates.
+fn unique_paths(template: &Template, templates: &[Template], prefix: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    for (name, data_type) in template.schema.iter() {
+        let path = match prefix.is_empty() {
+            true => name.clone(),
+            false => format!("{}.{}", prefix, name),
+        };
+        if template.constraints.get(name).is_some_and(|constraints| constraints.contains(&Constraint::Unique)) {
+            paths.push(path.clone());
+        }
+        if let DataType::TEMPLATE(embedded) = data_type {
+            if let Some(embedded) = templates.iter().find(|t| t.template.as_ref() == Some(embedded)) {
+                paths.extend(unique_paths(embedded, templates, &path));
+            }
+        }
+    }
+    paths
+}
+
+/// Converts the value to the type of the field or list or fails if it doesn't fit.
+///
+/// `target` describes the field or list in the error message.
+fn coerce(value: Data, data_type: &DataType, target: impl Fn() -> String) -> Result<Data, RequestError> {
+    let kind = value.kind();
+    value.coerce(data_type).ok_or_else(|| RequestError::TypeMismatch(format!(
+        "{} expects {}, the value is of type {}.", target(), data_type, kind,
+    )))
+}
+
+/// Like [`coerce`], but unlike fields lists never hold null.
+fn coerce_item(value: Data, data_type: &DataType, target: impl Fn() -> String) -> Result<Data, RequestError> {
+    if value == Data::Null {
+        return Err(RequestError::TypeMismatch(format!("{} can't hold null items.", target())));
+    }
+    coerce(value, data_type, target)
+}
+
+/// Returns the type of the items of the list at the path.
+fn item_type<'a>(template: &'a Template, templates: &'a [Template], path: &str) -> Result<&'a DataType, RequestError> {
+    match template.field_type(path, templates) {
+        Some(DataType::LIST(element)) => Ok(element),
+        Some(data_type) => Err(RequestError::TypeMismatch(format!(
+            "Field \"{}\" is of type {}, not a list.", path, data_type,
+        ))),
+        None => Err(RequestError::FieldNonExistent(path.to_string())),
+    }
+}
+
+/// Returns the items of the list at the path, a null list starts out empty.
+fn list<'a>(instance: &'a mut Template, path: &str) -> Result<&'a mut Vec<Data>, RequestError> {
+    let data = instance.get_mut(path)
+        .ok_or_else(|| RequestError::FieldNonExistent(path.to_string()))?;
+    if *data == Data::Null {
+        *data = Data::List(Vec::new());
+    }
+    match data {
+        Data::List(items) => Ok(items),
+        _ => Err(RequestError::TypeMismatch(format!("Field \"{}\" isn't a list.", path))),
+    }
+}
+
+/// Gives the null embedded templates on the path the starting values of their template again,
+/// so a field inside them can be set like [`list`] lets null lists be appended to.
+fn embed(instance: &mut Template, template: &Template, templates: &[Template], path: &str) {
+    for (end, _) in path.match_indices('.') {
+        let parent = &path[..end];
+        let embedded = match template.field_type(parent, templates) {
+            Some(DataType::TEMPLATE(embedded)) => templates.iter().find(|t| t.template.as_ref() == Some(embedded)),
+            _ => None,
+        };
+        if let (Some(embedded), Some(data @ Data::Null)) = (embedded, instance.get_mut(parent)) {
+            *data = Data::Object(embedded.data.clone());
+        }
+    }
+}
+
+/// Changes the fields of the template, of the templates derived from it and of every value of them,
+/// including the ones embedded by other templates.
+///
+/// The change is recorded as a new version of every changed template, then every instance is migrated to the new version of its template.
+/// This happens on copies of the templates and instances,
+/// which only replace the stored ones if every migrated instance still meets the constraints.
+fn alter_template(name: String, alteration: Alteration, context: &Context) -> Result<(), RequestError> {
+    context.authorize(Permission::Create, &name)?;
+    match &alteration {
+        Alteration::Add(decl) => authorize_embedding(&decl.data_type, context)?,
+        Alteration::Modify { data_type, .. } => authorize_embedding(data_type, context)?,
+        Alteration::Drop(_) | Alteration::Rename { .. } => (),
+    }
+    let mut templates = lock(&TEMPLATES);
+    let mut instances = lock(&INSTANCES);
+    let template = templates.iter()
+        .find(|t| t.template.as_ref() == Some(&name))
+        .ok_or(RequestError::TemplateNonExistent)?;
+    // The template and every template derived from it
+    let family = templates.iter()
+        .filter(|t| t.template.as_ref() == Some(&name) || derives(t, &name, &templates))
+        .filter_map(|t| t.template.clone())
+        .collect::<Vec<String>>();
+
+    let field = match &alteration {
+        Alteration::Add(decl) => &decl.name,
+        Alteration::Drop(field) | Alteration::Rename { field, .. } | Alteration::Modify { field, .. } => field,
+    };
+    let parent = template.extends.as_ref()
+        .and_then(|parent| templates.iter().find(|t| t.template.as_ref() == Some(parent)));
+    if let Some(parent) = parent.filter(|parent| parent.schema.contains_key(field)) {
+        return Err(RequestError::FieldInherited {
+            field: field.clone(),
+            template: parent.template.clone().unwrap_or_default(),
+        });
+    }
+
+    let mut constraints = Vec::new();
+    let change = match alteration {
+        Alteration::Add(decl) => {
+            if family.iter().any(|name| embeds(&decl.data_type, name, &templates)) {
+                return Err(RequestError::TypeMismatch(format!("Type \"{}\" can't embed itself.", name)));
+            }
+            let field = decl.name.clone();
+            let data_type = decl.data_type.clone();
+            let added = create_template(TemplateDecl { name: name.clone(), parent: None, fields: vec![decl] }, &templates)?;
+            constraints = added.constraints.get(&field).cloned().unwrap_or_default();
+            let value = added.data.get(&field).cloned().unwrap_or(Data::Null);
+            Change::Add { field, data_type, value }
+        },
+        Alteration::Drop(field) => {
+            if !template.schema.contains_key(&field) {
+                return Err(RequestError::FieldNonExistent(field));
+            }
+            Change::Drop { field }
+        },
+        Alteration::Rename { field, to } => {
+            if !template.schema.contains_key(&field) {
+                return Err(RequestError::FieldNonExistent(field));
+            }
+            Change::Rename { field, to }
+        },
+        Alteration::Modify { field, data_type } => {
+            let current = template.schema.get(&field)
+                .ok_or_else(|| RequestError::FieldNonExistent(field.clone()))?;
+            if *current == data_type {
+                return Ok(());
+            }
+            if family.iter().any(|name| embeds(&data_type, name, &templates)) {
+                return Err(RequestError::TypeMismatch(format!("Type \"{}\" can't embed itself.", name)));
+            }
+            if let Some(constraint) = template.constraints.get(&field).into_iter().flatten().find(|c| !c.fits(&data_type)) {
+                return Err(RequestError::TypeMismatch(format!(
+                    "{} doesn't apply to field \"{}\" of type {}.", constraint, field, data_type,
+                )));
+            }
+            Change::Modify { field, data_type }
+        },
+    };
+
+    let mut altered = templates.clone();
+    for other in altered.iter_mut() {
+        let paths = match other.template.as_ref().is_some_and(|name| family.contains(name)) {
+            true => {
+                reshape(other, &change, &constraints)?;
+                vec![String::new()]
             },
-            None => return Err(RequestError::SyntaxError),
+            false => family.iter()
+                .flat_map(|name| embedded_paths(&other.schema, name, &templates, ""))
+                .collect(),
+        };
+        if paths.is_empty() {
+            continue;
+        }
+        other.version += 1;
+        for path in paths {
+            let revision = Revision { version: other.version, user: context.user.clone(), path, change: change.clone() };
+            revision.apply(other).map_err(RequestError::TypeMismatch)?;
+            other.history.push(revision);
+        }
+    }
+
+    let mut migrated = instances.clone();
+    for instance in migrated.iter_mut() {
+        if let Some(template) = altered.iter().find(|t| t.template == instance.template) {
+            instance.migrate(template).map_err(RequestError::TypeMismatch)?;
+        }
+    }
+    // The unique values are collected once per template, not once per instance
+    let mut unique = HashMap::new();
+    for (i, instance) in migrated.iter().enumerate() {
+        if instance.data == instances[i].data {
+            continue;
+        }
+        let template = altered.iter()
+            .find(|t| t.template == instance.template)
+            .ok_or(RequestError::TemplateNonExistent)?;
+        let unique = unique.entry(instance.template.clone())
+            .or_insert_with(|| UniqueValues::new(template, &altered, migrated.iter()));
+        validate(&instance.data, template, "", &altered, unique)?;
+    }
+
+    *templates = altered;
+    *instances = migrated;
+    Ok(())
+}
+
+/// Changes the schema and constraints of the template the same way the change alters its values.
+///
+/// `constraints` are the constraints of an added field.
+fn reshape(template: &mut Template, change: &Change, constraints: &[Constraint]) -> Result<(), RequestError> {
+    match change {
+        Change::Add { field, data_type, .. } => {
+            if template.schema.contains_key(field) {
+                return Err(RequestError::FieldAlreadyExists(field.clone()));
+            }
+            template.schema.insert(field.clone(), data_type.clone());
+            if !constraints.is_empty() {
+                template.constraints.insert(field.clone(), constraints.to_vec());
+            }
+        },
+        Change::Drop { field } => {
+            template.schema.remove(field);
+            template.constraints.remove(field);
+        },
+        Change::Rename { field, to } => {
+            if template.schema.contains_key(to) {
+                return Err(RequestError::FieldAlreadyExists(to.clone()));
+            }
+            rename(&mut template.schema, field, to);
+            rename(&mut template.constraints, field, to);
+        },
+        Change::Modify { field, data_type } => {
+            if let Some(current) = template.schema.get_mut(field) {
+                *current = data_type.clone();
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Returns true if the template extends the template named `name`, directly or through other templates.
+fn derives(template: &Template, name: &str, templates: &[Template]) -> bool {
+    match &template.extends {
+        Some(parent) => parent == name || templates.iter()
+            .find(|t| t.template.as_ref() == Some(parent))
+            .is_some_and(|parent| derives(parent, name, templates)),
+        None => false,
+    }
+}
+
+/// Returns the paths of every value of the template named `name` embedded in values that follow the schema.
+fn embedded_paths(schema: &LinkedHashMap<String, DataType>, name: &str, templates: &[Template], prefix: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    for (field, data_type) in schema.iter() {
+        if let DataType::TEMPLATE(embedded) = data_type {
+            let path = match prefix.is_empty() {
+                true => field.clone(),
+                false => format!("{}.{}", prefix, field),
+            };
+            if embedded == name {
+                paths.push(path);
+            } else if let Some(template) = templates.iter().find(|t| t.template.as_ref() == Some(embedded)) {
+                paths.extend(embedded_paths(&template.schema, name, templates, &path));
+            }
+        }
+    }
+    paths
+}
+
+/// Returns true if values of the type embed the template named `name`, directly or through other templates.
+fn embeds(data_type: &DataType, name: &str, templates: &[Template]) -> bool {
+    match data_type {
+        DataType::TEMPLATE(embedded) => embedded == name || templates.iter()
+            .find(|t| t.template.as_ref() == Some(embedded))
+            .is_some_and(|t| t.schema.values().any(|data_type| embeds(data_type, name, templates))),
+        _ => false,
+    }
+}
+
+/// Returns the index of the instance with the given name.
+///
+/// Instances of templates the user has no permission on at all don't exist for the user,
+/// so users can't find out which names are taken by trying them.
+fn find_instance(instances: &[Template], name: &str, context: &Context) -> Result<usize, RequestError> {
+    instances.iter()
+        .position(|i| i.instance.as_deref() == Some(name))
+        .filter(|index| context.knows(instances[*index].template.as_deref().unwrap_or_default()))
+        .ok_or(RequestError::InstanceNonExistent)
+}
+
+fn delete_instance(name: String, context: &Context) -> Result<(), RequestError> {
+    let mut mutex = lock(&INSTANCES);
+    let index = find_instance(&mutex, &name, context)?;
+    context.authorize(Permission::Delete, mutex[index].template.as_deref().unwrap_or_default())?;
+    mutex.remove(index);
+    Ok(())
+}
+
+/// Deletes the template, every instance of it and the permissions granted on it.
+fn delete_template(name: String, context: &Context) -> Result<(), RequestError> {
+    context.authorize(Permission::Delete, &name)?;
+    let mut templates = lock(&TEMPLATES);
+    let index = templates.iter()
+        .position(|t| t.template.as_ref() == Some(&name))
+        .ok_or(RequestError::TemplateNonExistent)?;
+    let embedded = DataType::TEMPLATE(name.clone());
+    let in_use = |t: &&Template| t.extends.as_ref() == Some(&name) || t.schema.values().any(|data_type| *data_type == embedded);
+    if let Some(user) = templates.iter().find(in_use) {
+        return Err(RequestError::TemplateInUse(user.template.clone().unwrap_or_default()));
+    }
+    templates.remove(index);
+    let mut instances = lock(&INSTANCES);
+    instances.retain(|i| i.template.as_ref() != Some(&name));
+    // A template declared later under the same name must not inherit the grants
+    lock(&GRANTS).retain(|grant| grant.template != name);
+    Ok(())
+}
+
+/// Returns every template the user is allowed to read, without their history.
+fn query_templates(context: &Context) -> Vec<Template> {
+    lock(&TEMPLATES).iter()
+        .filter(|t| context.is_allowed(Permission::Read, t.template.as_deref().unwrap_or_default()))
+        .map(|t| Template { history: Vec::new(), ..t.clone() })
+        .collect()
+}
+
+/// Returns every instance of the template, and of the templates derived from it the user is allowed to read.
+///
+/// Only instances that meet the condition are returned in the order of the query and by their name,
+/// projected onto the fields of the query, which have to be part of the template.
+/// List items missing from an instance are null.
+fn query_type(query: TypeQuery, context: &Context) -> Result<Vec<Output>, RequestError> {
+    context.authorize(Permission::Read, &query.template)?;
+    let templates = lock(&TEMPLATES);
+    let template = templates.iter()
+        .find(|t| t.template.as_ref() == Some(&query.template))
+        .ok_or(RequestError::TemplateNonExistent)?;
+    if let Some(field) = query.fields.iter().find(|field| template.field_type(field, &templates).is_none()) {
+        return Err(RequestError::FieldNonExistent(field.clone()));
+    }
+    if let Some(condition) = &query.condition {
+        condition.check(template, &templates)?;
+    }
+    for key in query.order.iter() {
+        match template.field_type(&key.field, &templates) {
+            None => return Err(RequestError::FieldNonExistent(key.field.clone())),
+            Some(data_type @ (DataType::TEMPLATE(_) | DataType::LIST(_))) => return Err(RequestError::TypeMismatch(format!(
+                "Instances can't be ordered by field \"{}\" of type {}.", key.field, data_type,
+            ))),
+            Some(_) => {},
         }
     }
+    let names = queried_templates(&query.template, query.derived, &templates, context);
+
+    let after = match query.cursor.as_ref().and_then(|cursor| cursor.after.as_ref()) {
+        Some(token) => Some(Position::decode(token, &query, template, &templates)?),
+        None => None,
+    };
+
+    let instances = lock(&INSTANCES);
+    let mut matching = instances.iter()
+        .filter(|i| i.template.as_ref().is_some_and(|template| names.contains(&template)))
+        .filter(|i| query.condition.as_ref().is_none_or(|condition| condition.test(i)))
+        .map(|i| (Position::of(i, &query.order), i))
+        .collect::<Vec<(Position, &Template)>>();
+    matching.sort_by(|(a, _), (b, _)| a.cmp(b, &query.order));
+    let select = |instance: &Template| match query.fields.is_empty() {
+        true => instance.clone(),
+        false => {
+            let data = query.fields.iter()
+                .map(|field| (field.clone(), instance.get(field).cloned().unwrap_or(Data::Null)))
+                .collect();
+            Template { data, ..instance.clone() }
+        },
+    };
+
+    let cursor = match &query.cursor {
+        Some(cursor) => cursor,
+        None => return Ok(matching.into_iter()
+            .skip(query.offset)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .map(|(_, instance)| Output::Template(select(instance)))
+            .collect()),
+    };
+    let mut page = matching.into_iter()
+        .filter(|(position, _)| after.as_ref().is_none_or(|after| position.cmp(after, &query.order) == Ordering::Greater))
+        .take(cursor.size.saturating_add(1))
+        .collect::<Vec<(Position, &Template)>>();
+    // The page is followed by another one if there is an instance left over
+    let next = match page.len() > cursor.size {
+        true => {
+            page.truncate(cursor.size);
+            page.last().map(|(position, _)| position.encode(&query)).transpose()?
+        },
+        false => None,
+    };
+    Ok(vec![Output::Page(Page {
+        instances: page.iter().map(|(_, instance)| select(instance)).collect(),
+        cursor: next,
+    })])
+}
+
+/// Summarizes the instances of a template, see [`AggregateQuery::aggregate`].
+fn query_aggregate(query: AggregateQuery, context: &Context) -> Result<Aggregation, RequestError> {
+    context.authorize(Permission::Read, &query.template)?;
+    let templates = lock(&TEMPLATES);
+    let template = templates.iter()
+        .find(|t| t.template.as_ref() == Some(&query.template))
+        .ok_or(RequestError::TemplateNonExistent)?;
+    query.check(template, &templates)?;
+    if let Some(condition) = &query.condition {
+        condition.check(template, &templates)?;
+    }
+    let names = queried_templates(&query.template, query.derived, &templates, context);
+
+    let instances = lock(&INSTANCES);
+    let matching = instances.iter()
+        .filter(|i| i.template.as_ref().is_some_and(|template| names.contains(&template)))
+        .filter(|i| query.condition.as_ref().is_none_or(|condition| condition.test(i)))
+        .collect::<Vec<&Template>>();
+    let groups = query.aggregate(matching)?;
+    Ok(Aggregation { template: query.template, groups })
+}
+
+/// The names of the template and, with `DERIVED`, of the templates derived from it that the user may read.
+fn queried_templates<'a>(name: &'a String, derived: bool, templates: &'a [Template], context: &Context) -> Vec<&'a String> {
+    templates.iter()
+        .filter(|t| derived && derives(t, name, templates))
+        .filter(|t| context.is_allowed(Permission::Read, t.template.as_deref().unwrap_or_default()))
+        .filter_map(|t| t.template.as_ref())
+        .chain(std::iter::once(name))
+        .collect()
+}
+
+/// Returns the template with every change made to it.
+fn query_history(name: String, context: &Context) -> Result<Template, RequestError> {
+    context.authorize(Permission::Read, &name)?;
+    lock(&TEMPLATES).iter()
+        .find(|t| t.template.as_ref() == Some(&name))
+        .cloned()
+        .ok_or(RequestError::TemplateNonExistent)
+}
 
+fn grant(grant: Grant, context: &Context) -> Result<(), RequestError> {
+    context.authorize_admin()?;
+    let mut grants = lock(&GRANTS);
+    if !grants.contains(&grant) {
+        grants.push(grant);
+    }
+    Ok(())
+}
+
+fn revoke(grant: Grant, context: &Context) -> Result<(), RequestError> {
+    context.authorize_admin()?;
+    lock(&GRANTS).retain(|g| *g != grant);
+    Ok(())
+}
+
+/// Executes the statements from the query on behalf of the user in the [`Context`].
+///
+/// Every statement checks the permissions of the user before it changes or returns anything.
+pub fn execute_statements(statements: Vec<Statement>, context: &Context) -> Result<Vec<Output>, RequestError> {
+    let mut output: Vec<Output> = Vec::new();
+    for statement in statements {
+        match statement {
+            Statement::DeclareTemplate(decl) => declare_template(decl, context)?,
+            Statement::Create { instance, template, actions } => create_instance(instance, template, actions, context)?,
+            Statement::Query { instance, condition, actions } => output.extend(query_instance(instance, condition, actions, context)?.into_iter().map(Output::Template)),
+            Statement::QueryTemplates => output.extend(query_templates(context).into_iter().map(Output::Template)),
+            Statement::QueryType(query) => output.extend(query_type(query, context)?),
+            Statement::QueryAggregate(query) => output.push(Output::Aggregation(query_aggregate(query, context)?)),
+            Statement::QueryHistory(name) => output.push(Output::Template(query_history(name, context)?)),
+            Statement::AlterTemplate { template, alteration } => alter_template(template, alteration, context)?,
+            Statement::DeleteInstance(name) => delete_instance(name, context)?,
+            Statement::DeleteTemplate(name) => delete_template(name, context)?,
+            Statement::Grant(g) => grant(g, context)?,
+            Statement::Revoke(g) => revoke(g, context)?,
+        }
+    }
     Ok(output)
 }
 
+/// An entry of the response, serialized without a tag.
+///
+/// Outputs only live until the response is serialized, so templates aren't boxed.
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum Output {
+    Template(Template),
+    Page(Page),
+    Aggregation(Aggregation),
+}
+
 /// Query the parsed data from memory
-pub fn data(lines: Vec<Vec<TokenMatch>>) -> Result<String, RequestError> {
-    match serde_json::to_string_pretty(&execute_statements(lines)?) {
+pub fn data(tokens: Vec<TokenMatch>, context: &Context) -> Result<String, RequestError> {
+    let statements = parser::parse(tokens)?;
+    match serde_json::to_string_pretty(&execute_statements(statements, context)?) {
         Ok(value) => Ok(value),
         Err(_) => Err(RequestError::SerializationError),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer;
+
+    use super::*;
+
+    // Shared with the tests of the submodules, every test works on templates with names of its own
+
+    pub(super) fn admin() -> Context {
+        Context { user: "admin".to_string(), roles: vec![ADMIN_ROLE.to_string()] }
+    }
+
+    pub(super) fn user(role: &str) -> Context {
+        Context { user: role.to_string(), roles: vec![role.to_string()] }
+    }
+
+    /// Lexes, parses and executes the source like a request of the user.
+    pub(super) fn run(source: &str, context: &Context) -> Result<String, RequestError> {
+        data(lexer::parse(source.to_string()), context)
+    }
+
+    #[test]
+    fn creating_an_instance_of_a_missing_template_fails() {
+        let result = run("CREATE \"Orphan\" TYPE \"MISSING\";", &admin());
+        assert!(matches!(result, Err(RequestError::TemplateNonExistent)), "{:?}", result);
+        assert!(!lock(&INSTANCES).iter().any(|i| i.instance.as_deref() == Some("Orphan")));
+    }
+
+    #[test]
+    fn declaring_a_template_needs_read_on_its_sources() {
+        run("TYPE \"SOURCE_PARENT\"; NAME \"Secret\" TYPE STRING; END;", &admin()).unwrap();
+        run("TYPE \"SOURCE_PART\"; NAME \"Secret\" TYPE STRING; END;", &admin()).unwrap();
+        run("GRANT CREATE ON TYPE \"SOURCE_CHILD\" TO \"source-author\";", &admin()).unwrap();
+        run("GRANT CREATE ON TYPE \"SOURCE_OWNER\" TO \"source-author\";", &admin()).unwrap();
+        let author = user("source-author");
+
+        let extends = "TYPE \"SOURCE_CHILD\" EXTENDS \"SOURCE_PARENT\"; END;";
+        let embeds = "TYPE \"SOURCE_OWNER\"; NAME \"Part\" TYPE \"SOURCE_PART\"; END;";
+        assert!(matches!(run(extends, &author), Err(RequestError::PermissionDenied(_))));
+        assert!(matches!(run(embeds, &author), Err(RequestError::PermissionDenied(_))));
+
+        run("GRANT READ ON TYPE \"SOURCE_PARENT\" TO \"source-author\";", &admin()).unwrap();
+        run("GRANT READ ON TYPE \"SOURCE_PART\" TO \"source-author\";", &admin()).unwrap();
+        run(extends, &author).unwrap();
+        run(embeds, &author).unwrap();
+    }
+
+    #[test]
+    fn altering_a_template_needs_read_on_embedded_templates() {
+        run("TYPE \"ALTER_PART\"; NAME \"Secret\" TYPE STRING; END;", &admin()).unwrap();
+        run("TYPE \"ALTER_OWNER\"; NAME \"Part\" TYPE STRING; END;", &admin()).unwrap();
+        run("GRANT CREATE ON TYPE \"ALTER_OWNER\" TO \"alter-author\";", &admin()).unwrap();
+        let author = user("alter-author");
+
+        let add = "ALTER TYPE \"ALTER_OWNER\" ADD NAME \"Extra\" TYPE \"ALTER_PART\";";
+        let modify = "ALTER TYPE \"ALTER_OWNER\" MODIFY NAME \"Part\" TYPE \"ALTER_PART\";";
+        assert!(matches!(run(add, &author), Err(RequestError::PermissionDenied(_))));
+        assert!(matches!(run(modify, &author), Err(RequestError::PermissionDenied(_))));
+
+        run("GRANT READ ON TYPE \"ALTER_PART\" TO \"alter-author\";", &admin()).unwrap();
+        run(add, &author).unwrap();
+        run(modify, &author).unwrap();
+    }
+
+    #[test]
+    fn negated_conditions_skip_null_fields() {
+        run("TYPE \"NULLABLE\"; NAME \"First\" TYPE STRING; END;", &admin()).unwrap();
+        run("CREATE \"NullableMath\" TYPE \"NULLABLE\" THEN; SET \"First\" VALUE \"Math\"; END;", &admin()).unwrap();
+        run("CREATE \"NullableNone\" TYPE \"NULLABLE\";", &admin()).unwrap();
+
+        let output = run("QUERY TYPE \"NULLABLE\" WHERE NOT \"First\" = \"x\";", &admin()).unwrap();
+        assert!(output.contains("NullableMath") && !output.contains("NullableNone"), "{}", output);
+        let output = run("QUERY TYPE \"NULLABLE\" WHERE \"First\" NOT LIKE \"x%\" COUNT;", &admin()).unwrap();
+        assert!(output.contains("\"COUNT\": 1"), "{}", output);
+        let output = run("QUERY TYPE \"NULLABLE\" WHERE NOT \"First\" = \"x\" OR \"First\" IS NULL COUNT;", &admin()).unwrap();
+        assert!(output.contains("\"COUNT\": 2"), "{}", output);
+    }
+
+    #[test]
+    fn hides_instances_from_users_without_permissions() {
+        run("TYPE \"HIDDEN\"; NAME \"Secret\" TYPE STRING; END;", &admin()).unwrap();
+        run("CREATE \"Hidden\" TYPE \"HIDDEN\";", &admin()).unwrap();
+        run("GRANT READ ON TYPE \"HIDDEN\" TO \"hidden-reader\";", &admin()).unwrap();
+        let stranger = user("hidden-stranger");
+        for statement in ["QUERY \"Hidden\";", "QUERY \"Hidden\" THEN; SET \"Secret\" VALUE \"x\"; END;", "DELETE \"Hidden\";"] {
+            assert!(matches!(run(statement, &stranger), Err(RequestError::InstanceNonExistent)), "{}", statement);
+        }
+        assert!(matches!(run("QUERY \"Missing\";", &stranger), Err(RequestError::InstanceNonExistent)));
+
+        // Users who know the template learn which permission they lack
+        let reader = user("hidden-reader");
+        assert!(run("QUERY \"Hidden\";", &reader).is_ok());
+        assert!(matches!(run("DELETE \"Hidden\";", &reader), Err(RequestError::PermissionDenied(_))));
+    }
+
+    #[test]
+    fn deleting_a_template_revokes_its_grants() {
+        run("TYPE \"REDECLARED\"; NAME \"Secret\" TYPE STRING; END;", &admin()).unwrap();
+        run("GRANT READ ON TYPE \"REDECLARED\" TO \"redeclared-reader\";", &admin()).unwrap();
+        run("DELETE TYPE \"REDECLARED\";", &admin()).unwrap();
+        assert!(!lock(&GRANTS).iter().any(|grant| grant.template == "REDECLARED"));
+
+        run("TYPE \"REDECLARED\"; NAME \"Secret\" TYPE STRING; END;", &admin()).unwrap();
+        let query = "QUERY TYPE \"REDECLARED\";";
+        assert!(matches!(run(query, &user("redeclared-reader")), Err(RequestError::PermissionDenied(_))));
+    }
+
+    #[test]
+    fn changes_lists_item_by_item() {
+        run("TYPE \"TAGGED\"; NAME \"Tags\" TYPE LIST OF STRING; END;", &admin()).unwrap();
+        run("CREATE \"Tagged\" TYPE \"TAGGED\" THEN; APPEND \"Tags\" VALUE \"a\"; APPEND \"Tags\" VALUE \"b\"; END;", &admin()).unwrap();
+        run("QUERY \"Tagged\" THEN; APPEND \"Tags\" VALUE \"a\"; SET \"Tags[1]\" VALUE \"c\"; REMOVE \"Tags\" VALUE \"a\"; END;", &admin()).unwrap();
+        let tags = |instance: &Template| instance.data.get("Tags").cloned();
+        let instance = lock(&INSTANCES).iter().find(|i| i.instance.as_deref() == Some("Tagged")).cloned().unwrap();
+        assert_eq!(tags(&instance), Some(Data::List(vec![Data::from("c".to_string())])));
+
+        let change = |action: &str| run(&format!("QUERY \"Tagged\" THEN; {}; END;", action), &admin());
+        assert!(matches!(change("SET \"Tags[1]\" VALUE \"d\""), Err(RequestError::FieldNonExistent(_))));
+        for action in [
+            "SET \"Tags[0]\" VALUE 1",
+            "APPEND \"Tags\" VALUE 1",
+            "SET \"Tags[0]\" VALUE NULL",
+            "APPEND \"Tags\" VALUE NULL",
+        ] {
+            let result = change(action);
+            assert!(matches!(result, Err(RequestError::TypeMismatch(_))), "{}: {:?}", action, result);
+        }
+        let unchanged = lock(&INSTANCES).iter().find(|i| i.instance.as_deref() == Some("Tagged")).cloned().unwrap();
+        assert_eq!(tags(&unchanged), tags(&instance));
+    }
+
+    #[test]
+    fn checks_values_against_the_schema() {
+        run("TYPE \"MEASURED\"; NAME \"Weight\" TYPE FLOAT; NAME \"Count\" TYPE INTEGER; END;", &admin()).unwrap();
+        let result = run("CREATE \"MeasuredUnknown\" TYPE \"MEASURED\" THEN; SET \"Height\" VALUE 1.5; END;", &admin());
+        assert!(matches!(result, Err(RequestError::FieldNonExistent(field)) if field == "Height"));
+        let result = run("CREATE \"MeasuredMismatch\" TYPE \"MEASURED\" THEN; SET \"Count\" VALUE 1.5; END;", &admin());
+        assert!(matches!(result, Err(RequestError::TypeMismatch(_))), "{:?}", result);
+        assert!(!lock(&INSTANCES).iter().any(|i| i.template.as_deref() == Some("MEASURED")));
+
+        run("CREATE \"Measured\" TYPE \"MEASURED\" THEN; SET \"Weight\" VALUE 2; END;", &admin()).unwrap();
+        let instance = lock(&INSTANCES).iter().find(|i| i.instance.as_deref() == Some("Measured")).cloned().unwrap();
+        assert_eq!(instance.data.get("Weight"), Some(&Data::Float(2.0)));
+    }
+
+    #[test]
+    fn sets_fields_of_null_embedded_templates() {
+        run("TYPE \"COURSE\"; NAME \"Calories\" TYPE INTEGER; NAME \"Vegan\" TYPE BOOLEAN STARTING FALSE; END;", &admin()).unwrap();
+        run("TYPE \"MENU\"; NAME \"Lunch\" TYPE \"COURSE\"; END;", &admin()).unwrap();
+        run("CREATE \"Menu\" TYPE \"MENU\" THEN; SET \"Lunch\" VALUE NULL; END;", &admin()).unwrap();
+        run("QUERY \"Menu\" THEN; SET \"Lunch.Calories\" VALUE 7; END;", &admin()).unwrap();
+
+        let instance = lock(&INSTANCES).iter().find(|i| i.instance.as_deref() == Some("Menu")).cloned().unwrap();
+        assert_eq!(instance.get("Lunch.Calories"), Some(&Data::Integer(7)));
+        assert_eq!(instance.get("Lunch.Vegan"), Some(&Data::Boolean(false)));
+    }
+
+    #[test]
+    fn rejects_duplicate_unique_values() {
+        run("TYPE \"BADGE_HOLDER\"; NAME \"Badge\" TYPE INTEGER UNIQUE; END;", &admin()).unwrap();
+        run("CREATE \"HolderA\" TYPE \"BADGE_HOLDER\" THEN; SET \"Badge\" VALUE 1; END;", &admin()).unwrap();
+        run("CREATE \"HolderB\" TYPE \"BADGE_HOLDER\" THEN; SET \"Badge\" VALUE 2; END;", &admin()).unwrap();
+        run("CREATE \"HolderC\" TYPE \"BADGE_HOLDER\";", &admin()).unwrap();
+        run("CREATE \"HolderD\" TYPE \"BADGE_HOLDER\";", &admin()).unwrap();
+
+        let result = run("CREATE \"HolderE\" TYPE \"BADGE_HOLDER\" THEN; SET \"Badge\" VALUE 1; END;", &admin());
+        assert!(matches!(result, Err(RequestError::ConstraintViolation { field, .. }) if field == "Badge"));
+        let result = run("QUERY \"HolderB\" THEN; SET \"Badge\" VALUE 1; END;", &admin());
+        assert!(matches!(result, Err(RequestError::ConstraintViolation { .. })), "{:?}", result);
+        // The value an instance is changed from no longer counts
+        run("QUERY \"HolderA\" THEN; SET \"Badge\" VALUE 3; END;", &admin()).unwrap();
+        run("QUERY \"HolderB\" THEN; SET \"Badge\" VALUE 1; END;", &admin()).unwrap();
+    }
+
+    /// Returns the fields of the instance.
+    fn fields(name: &str) -> LinkedHashMap<String, Data> {
+        lock(&INSTANCES).iter().find(|i| i.instance.as_deref() == Some(name)).map(|i| i.data.clone()).unwrap()
+    }
+
+    #[test]
+    fn alters_templates_and_their_instances() {
+        run("TYPE \"ALTERED\"; NAME \"Code\" TYPE STRING; NAME \"Old\" TYPE STRING; END;", &admin()).unwrap();
+        run("CREATE \"AlteredA\" TYPE \"ALTERED\" THEN; SET \"Code\" VALUE \"1\"; SET \"Old\" VALUE \"x\"; END;", &admin()).unwrap();
+        run("CREATE \"AlteredB\" TYPE \"ALTERED\" THEN; SET \"Code\" VALUE \"2\"; END;", &admin()).unwrap();
+
+        run("ALTER TYPE \"ALTERED\" ADD NAME \"Note\" TYPE STRING STARTING \"none\";", &admin()).unwrap();
+        run("ALTER TYPE \"ALTERED\" DROP NAME \"Old\";", &admin()).unwrap();
+        run("ALTER TYPE \"ALTERED\" RENAME NAME \"Note\" TO \"Remark\";", &admin()).unwrap();
+        run("ALTER TYPE \"ALTERED\" MODIFY NAME \"Code\" TYPE INTEGER;", &admin()).unwrap();
+
+        for (name, code) in [("AlteredA", 1), ("AlteredB", 2)] {
+            let expected = [("Code", Data::Integer(code)), ("Remark", Data::from("none".to_string()))];
+            assert_eq!(fields(name), expected.into_iter().map(|(field, value)| (field.to_string(), value)).collect());
+        }
+        let templates = lock(&TEMPLATES);
+        let template = templates.iter().find(|t| t.template.as_deref() == Some("ALTERED")).unwrap();
+        assert_eq!(template.schema.get("Code"), Some(&DataType::INTEGER));
+        assert_eq!((template.version, template.history.len()), (5, 4));
+        assert!(lock(&INSTANCES).iter().filter(|i| i.template == template.template).all(|i| i.version == 5));
+    }
+
+    #[test]
+    fn rolls_back_alterations_an_instance_fails() {
+        run("TYPE \"ROLLED_BACK\"; NAME \"Code\" TYPE STRING; END;", &admin()).unwrap();
+        run("CREATE \"RolledBackA\" TYPE \"ROLLED_BACK\" THEN; SET \"Code\" VALUE \"1\"; END;", &admin()).unwrap();
+        run("CREATE \"RolledBackB\" TYPE \"ROLLED_BACK\" THEN; SET \"Code\" VALUE \"x\"; END;", &admin()).unwrap();
+        let template = || lock(&TEMPLATES).iter().find(|t| t.template.as_deref() == Some("ROLLED_BACK")).cloned().unwrap();
+        let before = (template(), fields("RolledBackA"), fields("RolledBackB"));
+
+        let result = run("ALTER TYPE \"ROLLED_BACK\" MODIFY NAME \"Code\" TYPE INTEGER;", &admin());
+        assert!(matches!(result, Err(RequestError::TypeMismatch(_))), "{:?}", result);
+        let result = run("ALTER TYPE \"ROLLED_BACK\" ADD NAME \"Key\" TYPE STRING STARTING \"same\" UNIQUE;", &admin());
+        assert!(matches!(result, Err(RequestError::ConstraintViolation { field, .. }) if field == "Key"));
+        assert_eq!((template(), fields("RolledBackA"), fields("RolledBackB")), before);
+    }
+
+    #[test]
+    fn derived_templates_inherit_their_parents_fields() {
+        run("TYPE \"VEHICLE\"; NAME \"Wheels\" TYPE INTEGER STARTING 4; END;", &admin()).unwrap();
+        run("TYPE \"TRUCK\" EXTENDS \"VEHICLE\"; NAME \"Load\" TYPE FLOAT; END;", &admin()).unwrap();
+        run("CREATE \"Vehicle\" TYPE \"VEHICLE\";", &admin()).unwrap();
+        run("CREATE \"Truck\" TYPE \"TRUCK\" THEN; SET \"Load\" VALUE 7.5; END;", &admin()).unwrap();
+        let expected = [("Wheels", Data::Integer(4)), ("Load", Data::Float(7.5))];
+        assert_eq!(fields("Truck"), expected.into_iter().map(|(field, value)| (field.to_string(), value)).collect());
+
+        let result = run("TYPE \"BIKE\" EXTENDS \"VEHICLE\"; NAME \"Wheels\" TYPE INTEGER STARTING 2; END;", &admin());
+        assert!(matches!(result, Err(RequestError::FieldAlreadyExists(field)) if field == "Wheels"));
+        let result = run("ALTER TYPE \"TRUCK\" DROP NAME \"Wheels\";", &admin());
+        assert!(matches!(result, Err(RequestError::FieldInherited { template, .. }) if template == "VEHICLE"));
+
+        let output = run("QUERY TYPE \"VEHICLE\";", &admin()).unwrap();
+        assert!(output.contains("\"Vehicle\"") && !output.contains("\"Truck\""), "{}", output);
+        let output = run("QUERY TYPE \"VEHICLE\" DERIVED;", &admin()).unwrap();
+        assert!(output.contains("\"Vehicle\"") && output.contains("\"Truck\""), "{}", output);
+        // Derived templates the user can't read are left out
+        run("GRANT READ ON TYPE \"VEHICLE\" TO \"vehicle-reader\";", &admin()).unwrap();
+        let output = run("QUERY TYPE \"VEHICLE\" DERIVED;", &user("vehicle-reader")).unwrap();
+        assert!(output.contains("\"Vehicle\"") && !output.contains("\"Truck\""), "{}", output);
+    }
+
+    #[test]
+    fn projects_instances_onto_the_fields_of_the_query() {
+        run("TYPE \"PROJECTED\"; NAME \"Kept\" TYPE STRING STARTING \"k\"; NAME \"Skipped\" TYPE STRING STARTING \"s\"; END;", &admin()).unwrap();
+        run("CREATE \"Projected\" TYPE \"PROJECTED\";", &admin()).unwrap();
+
+        let output = run("QUERY TYPE \"PROJECTED\" GET \"Kept\";", &admin()).unwrap();
+        assert!(output.contains("\"Kept\"") && !output.contains("\"Skipped\""), "{}", output);
+        let output = run("QUERY TYPE \"PROJECTED\";", &admin()).unwrap();
+        assert!(output.contains("\"Kept\"") && output.contains("\"Skipped\""), "{}", output);
+
+        // Without a template the query still lists the templates
+        let output = run("QUERY TYPE;", &admin()).unwrap();
+        assert!(output.contains("\"PROJECTED\"") && !output.contains("\"Projected\""), "{}", output);
+    }
+}
@@ -0,0 +1,187 @@
+use std::cmp::Ordering;
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use serde::{Serialize, Deserialize};
+
+use crate::data::{template::Template, serialization::Data};
+
+use super::{ast::{TypeQuery, SortKey}, error::RequestError};
+
+/// A page of a type query in `CURSOR` mode.
+#[derive(Serialize, Debug)]
+pub struct Page {
+    pub instances: Vec<Template>,
+    /// Passed to `AFTER` to get the next page, `null` on the last page.
+    pub cursor: Option<String>,
+}
+
+/// Where an instance is in the order of a type query: the values of its sort keys and its name.
+///
+/// A page continues right after the position of the last instance of the previous one,
+/// so instances that are created or deleted in the meantime don't shift the pages.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Position {
+    values: Vec<Data>,
+    instance: String,
+}
+
+/// The position of the last instance of a page, handed to the client as an opaque base64 token.
+#[derive(Serialize, Deserialize)]
+struct Token {
+    /// The template and sort keys of the query, a token only continues the query that returned it.
+    query: String,
+    #[serde(flatten)]
+    position: Position,
+}
+
+impl Position {
+    pub fn of(instance: &Template, keys: &[SortKey]) -> Self {
+        Position {
+            values: keys.iter()
+                .map(|key| instance.get(&key.field).cloned().unwrap_or(Data::Null))
+                .collect(),
+            instance: instance.instance.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Orders by the sort keys and then by the name of the instance.
+    pub fn cmp(&self, other: &Position, keys: &[SortKey]) -> Ordering {
+        compare_values(&self.values, &other.values, keys).then_with(|| self.instance.cmp(&other.instance))
+    }
+
+    pub fn encode(&self, query: &TypeQuery) -> Result<String, RequestError> {
+        let token = Token { query: fingerprint(query), position: self.clone() };
+        match serde_json::to_vec(&token) {
+            Ok(json) => Ok(BASE64.encode(json)),
+            Err(_) => Err(RequestError::SerializationError),
+        }
+    }
+
+    /// Reads the position from a token of the same query, the values are restored to the types of their fields.
+    pub fn decode(token: &str, query: &TypeQuery, template: &Template, templates: &[Template]) -> Result<Self, RequestError> {
+        let invalid = || RequestError::InvalidCursor(token.to_string());
+        let decoded: Token = BASE64.decode(token).ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(invalid)?;
+        if decoded.query != fingerprint(query) || decoded.position.values.len() != query.order.len() {
+            return Err(invalid());
+        }
+        let values = decoded.position.values.into_iter()
+            .zip(query.order.iter())
+            .map(|(value, key)| {
+                let data_type = template.field_type(&key.field, templates)?;
+                value.restore(data_type, templates).ok()
+            })
+            .collect::<Option<Vec<Data>>>()
+            .ok_or_else(invalid)?;
+        Ok(Position { values, instance: decoded.position.instance })
+    }
+}
+
+/// Orders the values of the sort keys by [`Data::total_cmp`], null values come last in both directions.
+pub fn compare_values(a: &[Data], b: &[Data], keys: &[SortKey]) -> Ordering {
+    for ((a, b), key) in a.iter().zip(b.iter()).zip(keys) {
+        let ordering = match (a, b) {
+            (Data::Null, Data::Null) => Ordering::Equal,
+            (Data::Null, _) => Ordering::Greater,
+            (_, Data::Null) => Ordering::Less,
+            (x, y) => match key.descending {
+                true => x.total_cmp(y).reverse(),
+                false => x.total_cmp(y),
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Identifies the order of a query, e.g. `"DAY" ORDER BY "Day" DESC`.
+fn fingerprint(query: &TypeQuery) -> String {
+    let keys = query.order.iter()
+        .map(|key| format!("{:?} {}", key.field, if key.descending { "DESC" } else { "ASC" }))
+        .collect::<Vec<String>>();
+    format!("{:?} ORDER BY {}", query.template, keys.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use crate::query::tests::{admin, run};
+
+    use super::*;
+
+    fn type_query(order: &[&str]) -> TypeQuery {
+        TypeQuery {
+            template: "SHIFT".to_string(),
+            derived: false,
+            condition: None,
+            order: order.iter().map(|field| SortKey { field: field.to_string(), descending: true }).collect(),
+            limit: None,
+            offset: 0,
+            cursor: None,
+            fields: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn decodes_encoded_positions() {
+        let template = Template::new("SHIFT".to_string())
+            .with_integer("Hours".to_string(), None)
+            .with_float("Rate".to_string(), None)
+            .with_timestamp("Start".to_string(), None)
+            .with_bytes("Badge".to_string(), None)
+            .build();
+        let instance = Template {
+            instance: Some("Night".to_string()),
+            ..Template::new("SHIFT".to_string())
+                .with_integer("Hours".to_string(), Some(8))
+                .with_float("Rate".to_string(), Some(1.5))
+                .with_timestamp("Start".to_string(), Some(datetime!(2022-05-02 22:00 UTC)))
+                .with_bytes("Badge".to_string(), Some(vec![0, 255]))
+                .build()
+        };
+        let query = type_query(&["Hours", "Rate", "Start", "Badge"]);
+        let position = Position::of(&instance, &query.order);
+
+        let token = position.encode(&query).unwrap();
+        let decoded = Position::decode(&token, &query, &template, &[]).unwrap();
+        assert_eq!(decoded.values, position.values);
+        assert_eq!(decoded.instance, "Night");
+        assert_eq!(decoded.cmp(&position, &query.order), Ordering::Equal);
+
+        let other = type_query(&["Hours"]);
+        assert!(matches!(Position::decode(&token, &other, &template, &[]), Err(RequestError::InvalidCursor(_))));
+        assert!(matches!(Position::decode("not base64", &query, &template, &[]), Err(RequestError::InvalidCursor(_))));
+    }
+
+    #[test]
+    fn continues_after_deleted_instances() {
+        run("TYPE \"PAGED\"; NAME \"Day\" TYPE INTEGER; END;", &admin()).unwrap();
+        for day in 1..=6 {
+            run(&format!("CREATE \"Paged{}\" TYPE \"PAGED\" THEN; SET \"Day\" VALUE {}; END;", day, day), &admin()).unwrap();
+        }
+        let page = |after: Option<&str>| {
+            let after = after.map(|cursor| format!(" AFTER \"{}\"", cursor)).unwrap_or_default();
+            let output = run(&format!("QUERY TYPE \"PAGED\" ORDER BY \"Day\" CURSOR 2{};", after), &admin()).unwrap();
+            let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+            let names = json[0]["instances"].as_array().unwrap().iter()
+                .map(|instance| instance["instance"].as_str().unwrap().to_string())
+                .collect::<Vec<String>>();
+            (names, json[0]["cursor"].as_str().map(str::to_string))
+        };
+
+        let (first, cursor) = page(None);
+        assert_eq!(first, ["Paged1", "Paged2"]);
+        // Neither the last instance of the page nor the first of the next one shift the pages
+        run("DELETE \"Paged2\";", &admin()).unwrap();
+        run("DELETE \"Paged3\";", &admin()).unwrap();
+        let (second, cursor) = page(cursor.as_deref());
+        assert_eq!(second, ["Paged4", "Paged5"]);
+        let (third, cursor) = page(cursor.as_deref());
+        assert_eq!(third, ["Paged6"]);
+        assert_eq!(cursor, None);
+    }
+}
@@ -0,0 +1,949 @@
+use crate::{lexer::data::{Token, TokenMatch}, data::{serialization::{DataType, parse_timestamp, parse_bytes}, constraint::{Constraint, Pattern}}, auth::{Grant, Permission}};
+
+use super::{ast::{Statement, TemplateDecl, FieldDecl, Action, Expr, Condition, Operator, Alteration, TypeQuery, SortKey, Cursor, AggregateQuery, Aggregate}, error::{RequestError, SyntaxError}};
+
+/// Parses the [`TokenMatch`]es from the lexer into a list of [`Statement`]s.
+pub fn parse(tokens: Vec<TokenMatch>) -> Result<Vec<Statement>, RequestError> {
+    Parser::new(tokens).statements()
+}
+
+/// How deep `NOT` and parentheses can nest in a condition.
+const MAX_NESTING: usize = 64;
+
+/// A recursive descent parser over the output of the lexer.
+struct Parser {
+    tokens: Vec<TokenMatch>,
+    position: usize,
+    /// Index of the first token of the statement currently being parsed.
+    statement_start: usize,
+    /// Number of `NOT` and parentheses around the condition currently being parsed.
+    nesting: usize,
+}
+
+impl Parser {
+    fn new(tokens: Vec<TokenMatch>) -> Self {
+        Self { tokens, position: 0, statement_start: 0, nesting: 0 }
+    }
+
+    /// Returns the next token without consuming it.
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).map(|tm| tm.token)
+    }
+
+    /// Consumes the next token, callers have to make sure that there is one.
+    fn bump(&mut self) -> TokenMatch {
+        let tm = self.tokens[self.position].clone();
+        self.position += 1;
+        tm
+    }
+
+    /// Consumes the next token if it is of the given kind.
+    fn accept(&mut self, token: Token) -> bool {
+        if self.peek() == Some(token) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    /// Consumes the next token and fails if it isn't of the given kind.
+    fn expect(&mut self, token: Token) -> Result<TokenMatch, RequestError> {
+        if self.peek() == Some(token) {
+            return Ok(self.bump());
+        }
+        Err(self.error(token))
+    }
+
+    /// Creates a [`SyntaxError`] pointing at the next token.
+    fn error(&self, expected: impl ToString) -> RequestError {
+        let found = self.tokens.get(self.position);
+        let (line, column) = match (found, self.tokens.last()) {
+            (Some(tm), _) => (tm.line, tm.column),
+            // Point right behind the last token at the end of the source
+            (None, Some(last)) => (last.line, last.column + last.to_string().chars().count()),
+            (None, None) => (1, 1),
+        };
+        let after = self.tokens[self.statement_start.max(self.position.saturating_sub(2))..self.position]
+            .iter()
+            .map(|tm| tm.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+        RequestError::SyntaxError(SyntaxError {
+            token: found.map(|tm| tm.to_string()),
+            after: if after.is_empty() { None } else { Some(after) },
+            expected: expected.to_string(),
+            line,
+            column,
+        })
+    }
+
+    fn literal(&mut self) -> Result<String, RequestError> {
+        Ok(self.expect(Token::Literal)?.value)
+    }
+
+    /// The name of a new field, which mustn't contain the `.` and `[` that separate the segments of a path.
+    fn field_name(&mut self) -> Result<String, RequestError> {
+        if self.peek() == Some(Token::Literal) && self.tokens[self.position].value.contains(['.', '[']) {
+            return Err(RequestError::InvalidLiteral(self.tokens[self.position].to_string()));
+        }
+        self.literal()
+    }
+
+    /// Every statement ends with a `;`, the last one in the source may omit it.
+    fn end_of_statement(&mut self) -> Result<(), RequestError> {
+        if self.peek().is_none() || self.accept(Token::ENDL) {
+            return Ok(());
+        }
+        Err(self.error(Token::ENDL))
+    }
+
+    fn statements(&mut self) -> Result<Vec<Statement>, RequestError> {
+        let mut statements = Vec::new();
+        while self.peek().is_some() {
+            // Skip empty statements
+            if self.accept(Token::ENDL) {
+                continue;
+            }
+            self.statement_start = self.position;
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement, RequestError> {
+        let statement = match self.peek() {
+            Some(Token::Type) => {
+                self.bump();
+                return self.declare_template();
+            },
+            Some(Token::Query) => {
+                self.bump();
+                return self.query();
+            },
+            Some(Token::Create) => {
+                self.bump();
+                return self.create();
+            },
+            Some(Token::Alter) => {
+                self.bump();
+                self.expect(Token::Type)?;
+                let template = self.literal()?;
+                Statement::AlterTemplate { template, alteration: self.alteration()? }
+            },
+            Some(Token::Delete) => {
+                self.bump();
+                if self.accept(Token::Type) {
+                    Statement::DeleteTemplate(self.literal()?)
+                } else {
+                    Statement::DeleteInstance(self.literal()?)
+                }
+            },
+            Some(Token::Grant) => {
+                self.bump();
+                Statement::Grant(self.grant(Token::To)?)
+            },
+            Some(Token::Revoke) => {
+                self.bump();
+                Statement::Revoke(self.grant(Token::From)?)
+            },
+            _ => return Err(self.error("TYPE, CREATE, QUERY, ALTER, DELETE, GRANT or REVOKE")),
+        };
+        self.end_of_statement()?;
+        Ok(statement)
+    }
+
+    /// `TYPE "name" [EXTENDS "parent"]; { NAME "field" TYPE <type> [STARTING <value>]; } END;`
+    fn declare_template(&mut self) -> Result<Statement, RequestError> {
+        let name = self.literal()?;
+        let parent = match self.accept(Token::Extends) {
+            true => Some(self.literal()?),
+            false => None,
+        };
+        self.expect(Token::ENDL)?;
+        let mut fields = Vec::new();
+        while !self.accept(Token::End) {
+            self.statement_start = self.position;
+            fields.push(self.field_decl()?);
+            self.expect(Token::ENDL)?;
+        }
+        self.end_of_statement()?;
+        Ok(Statement::DeclareTemplate(TemplateDecl { name, parent, fields }))
+    }
+
+    fn field_decl(&mut self) -> Result<FieldDecl, RequestError> {
+        if !self.accept(Token::Name) {
+            return Err(self.error("NAME or END"));
+        }
+        let name = self.field_name()?;
+        self.expect(Token::Type)?;
+        let data_type = self.data_type()?;
+        // Embedded templates start with the starting values of their template and lists empty
+        let starting = match !matches!(data_type, DataType::TEMPLATE(_) | DataType::LIST(_)) && self.accept(Token::Starting) {
+            true => Some(self.value_of(&data_type)?),
+            false => None,
+        };
+        let mut constraints = Vec::new();
+        while let Some(constraint) = self.constraint()? {
+            if !constraint.fits(&data_type) {
+                return Err(RequestError::TypeMismatch(format!(
+                    "{} doesn't apply to field \"{}\" of type {}.", constraint, name, data_type,
+                )));
+            }
+            constraints.push(constraint);
+        }
+        Ok(FieldDecl { name, data_type, starting, constraints })
+    }
+
+    /// `ADD NAME "field" TYPE <type> ...`, `DROP NAME "field"`, `RENAME NAME "field" TO "name"` or `MODIFY NAME "field" TYPE <type>`
+    fn alteration(&mut self) -> Result<Alteration, RequestError> {
+        match self.peek() {
+            Some(Token::Add) => {
+                self.bump();
+                if self.peek() != Some(Token::Name) {
+                    return Err(self.error(Token::Name));
+                }
+                self.field_decl().map(Alteration::Add)
+            },
+            Some(Token::Drop) => {
+                self.bump();
+                self.expect(Token::Name)?;
+                self.literal().map(Alteration::Drop)
+            },
+            Some(Token::Rename) => {
+                self.bump();
+                self.expect(Token::Name)?;
+                let field = self.literal()?;
+                self.expect(Token::To)?;
+                let to = self.field_name()?;
+                Ok(Alteration::Rename { field, to })
+            },
+            Some(Token::Modify) => {
+                self.bump();
+                self.expect(Token::Name)?;
+                let field = self.literal()?;
+                self.expect(Token::Type)?;
+                let data_type = self.data_type()?;
+                Ok(Alteration::Modify { field, data_type })
+            },
+            _ => Err(self.error("ADD, DROP, RENAME or MODIFY")),
+        }
+    }
+
+    /// Parses the next constraint of a field declaration, if there is one.
+    fn constraint(&mut self) -> Result<Option<Constraint>, RequestError> {
+        let constraint = match self.peek() {
+            Some(Token::Required) => {
+                self.bump();
+                Constraint::Required
+            },
+            Some(Token::Unique) => {
+                self.bump();
+                Constraint::Unique
+            },
+            Some(Token::Min) => {
+                self.bump();
+                Constraint::Min(self.number()?)
+            },
+            Some(Token::Max) => {
+                self.bump();
+                Constraint::Max(self.number()?)
+            },
+            Some(Token::Matches) => {
+                self.bump();
+                if self.peek() != Some(Token::Literal) {
+                    return Err(self.error(Token::Literal));
+                }
+                match Pattern::new(&self.tokens[self.position].value) {
+                    Ok(pattern) => {
+                        self.bump();
+                        Constraint::Matches(pattern)
+                    },
+                    Err(_) => return Err(RequestError::InvalidLiteral(self.tokens[self.position].to_string())),
+                }
+            },
+            Some(Token::MaxLen) => {
+                self.bump();
+                Constraint::MaxLen(self.count()?)
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(constraint))
+    }
+
+    /// A non-negative integer, like the number of characters of `MAXLEN`.
+    fn count(&mut self) -> Result<usize, RequestError> {
+        if self.peek() != Some(Token::Integer) {
+            return Err(self.error(Token::Integer));
+        }
+        match usize::try_from(self.integer()?) {
+            Ok(count) => Ok(count),
+            Err(_) => Err(RequestError::InvalidLiteral(self.tokens[self.position - 1].to_string())),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, RequestError> {
+        match self.peek() {
+            Some(Token::Integer) => self.integer().map(|value| value as f64),
+            Some(Token::Float) => self.float(),
+            _ => Err(self.error("a number")),
+        }
+    }
+
+    /// A scalar type, a template name or `LIST OF` a scalar type.
+    fn data_type(&mut self) -> Result<DataType, RequestError> {
+        match self.peek() {
+            Some(Token::Literal) => Ok(DataType::TEMPLATE(self.literal()?)),
+            Some(Token::List) => {
+                self.bump();
+                self.expect(Token::Of)?;
+                let element = self.scalar_type("STRING, INTEGER, FLOAT, BOOLEAN, TIMESTAMP or BYTES")?;
+                Ok(DataType::LIST(Box::new(element)))
+            },
+            _ => self.scalar_type("STRING, INTEGER, FLOAT, BOOLEAN, TIMESTAMP, BYTES, LIST or a template name"),
+        }
+    }
+
+    fn scalar_type(&mut self, expected: &str) -> Result<DataType, RequestError> {
+        let data_type = match self.peek() {
+            Some(Token::StringType) => DataType::STRING,
+            Some(Token::IntegerType) => DataType::INTEGER,
+            Some(Token::FloatType) => DataType::FLOAT,
+            Some(Token::BooleanType) => DataType::BOOLEAN,
+            Some(Token::TimestampType) => DataType::TIMESTAMP,
+            Some(Token::BytesType) => DataType::BYTES,
+            _ => return Err(self.error(expected)),
+        };
+        self.bump();
+        Ok(data_type)
+    }
+
+    /// `<permission> ON TYPE "template" TO "role"`, revocations use `FROM` instead of `TO`.
+    fn grant(&mut self, preposition: Token) -> Result<Grant, RequestError> {
+        let permission = self.permission()?;
+        self.expect(Token::On)?;
+        self.expect(Token::Type)?;
+        let template = self.literal()?;
+        self.expect(preposition)?;
+        let role = self.literal()?;
+        Ok(Grant { permission, template, role })
+    }
+
+    fn permission(&mut self) -> Result<Permission, RequestError> {
+        let permission = match self.peek() {
+            Some(Token::Read) => Permission::Read,
+            Some(Token::Write) => Permission::Write,
+            Some(Token::Create) => Permission::Create,
+            Some(Token::Delete) => Permission::Delete,
+            _ => return Err(self.error("READ, WRITE, CREATE or DELETE")),
+        };
+        self.bump();
+        Ok(permission)
+    }
+
+    /// `CREATE "instance" TYPE "template" [THEN; { <action>; } END];`
+    fn create(&mut self) -> Result<Statement, RequestError> {
+        let instance = self.literal()?;
+        self.expect(Token::Type)?;
+        let template = self.literal()?;
+        let mut actions = Vec::new();
+        if self.accept(Token::Then) {
+            self.expect(Token::ENDL)?;
+            while !self.accept(Token::End) {
+                self.statement_start = self.position;
+                if self.peek() == Some(Token::Get) {
+                    return Err(self.error("SET, APPEND, REMOVE or END"));
+                }
+                actions.push(self.action("SET, APPEND, REMOVE or END")?);
+                self.expect(Token::ENDL)?;
+            }
+        }
+        self.end_of_statement()?;
+        Ok(Statement::Create { instance, template, actions })
+    }
+
+    /// `QUERY TYPE;`, `QUERY TYPE "template" HISTORY;`, `QUERY TYPE "template" [DERIVED] [WHERE <condition>] [ORDER BY <sort key>, ...] [CURSOR n [AFTER "token"] | [LIMIT n] [OFFSET n]] [GET { "field" }];`, `QUERY TYPE "template" [DERIVED] [WHERE <condition>] <aggregate>, ... [GROUP BY "field", ...];` or `QUERY "instance" [WHERE <condition>] [<action> | THEN; { <action>; } END];`
+    fn query(&mut self) -> Result<Statement, RequestError> {
+        if self.accept(Token::Type) {
+            if self.peek() == Some(Token::Literal) {
+                let template = self.literal()?;
+                if self.accept(Token::History) {
+                    self.end_of_statement()?;
+                    return Ok(Statement::QueryHistory(template));
+                }
+                let derived = self.accept(Token::Derived);
+                let condition = match self.accept(Token::Where) {
+                    true => Some(self.condition()?),
+                    false => None,
+                };
+                if matches!(self.peek(), Some(Token::Count | Token::Sum | Token::Avg | Token::Min | Token::Max)) {
+                    let mut aggregates = vec![self.aggregate()?];
+                    while self.accept(Token::Comma) {
+                        aggregates.push(self.aggregate()?);
+                    }
+                    let mut groups = Vec::new();
+                    if self.accept(Token::Group) {
+                        self.expect(Token::By)?;
+                        groups.push(self.literal()?);
+                        while self.accept(Token::Comma) {
+                            groups.push(self.literal()?);
+                        }
+                    }
+                    self.end_of_statement()?;
+                    return Ok(Statement::QueryAggregate(AggregateQuery { template, derived, condition, aggregates, groups }));
+                }
+                let mut order = Vec::new();
+                if self.accept(Token::Order) {
+                    self.expect(Token::By)?;
+                    order.push(self.sort_key()?);
+                    while self.accept(Token::Comma) {
+                        order.push(self.sort_key()?);
+                    }
+                }
+                let (mut limit, mut offset, mut cursor) = (None, 0, None);
+                if self.accept(Token::Cursor) {
+                    // Empty pages would never get past the cursor
+                    let size = match self.count()? {
+                        0 => return Err(RequestError::InvalidLiteral(self.tokens[self.position - 1].to_string())),
+                        size => size,
+                    };
+                    let after = match self.accept(Token::After) {
+                        true => Some(self.literal()?),
+                        false => None,
+                    };
+                    cursor = Some(Cursor { size, after });
+                } else {
+                    if self.accept(Token::Limit) {
+                        limit = Some(self.count()?);
+                    }
+                    if self.accept(Token::Offset) {
+                        offset = self.count()?;
+                    }
+                }
+                let fields = match self.accept(Token::Get) {
+                    true => self.fields()?,
+                    false => Vec::new(),
+                };
+                self.end_of_statement()?;
+                return Ok(Statement::QueryType(TypeQuery { template, derived, condition, order, limit, offset, cursor, fields }));
+            }
+            self.end_of_statement()?;
+            return Ok(Statement::QueryTemplates);
+        }
+        let instance = self.literal()?;
+        let condition = match self.accept(Token::Where) {
+            true => Some(self.condition()?),
+            false => None,
+        };
+        let mut actions = Vec::new();
+        if self.accept(Token::Then) {
+            self.expect(Token::ENDL)?;
+            while !self.accept(Token::End) {
+                self.statement_start = self.position;
+                actions.push(self.action("GET, SET, APPEND, REMOVE or END")?);
+                self.expect(Token::ENDL)?;
+            }
+        } else if matches!(self.peek(), Some(Token::Get | Token::Set | Token::Append | Token::Remove)) {
+            actions.push(self.action("GET, SET, APPEND or REMOVE")?);
+        } else if self.peek().is_some() && self.peek() != Some(Token::ENDL) {
+            return Err(self.error("GET, SET, APPEND, REMOVE, THEN or ;"));
+        }
+        self.end_of_statement()?;
+        Ok(Statement::Query { instance, condition, actions })
+    }
+
+    /// `COUNT`, `SUM "field"`, `AVG "field"`, `MIN "field"` or `MAX "field"`
+    fn aggregate(&mut self) -> Result<Aggregate, RequestError> {
+        let aggregate: fn(String) -> Aggregate = match self.peek() {
+            Some(Token::Count) => {
+                self.bump();
+                return Ok(Aggregate::Count);
+            },
+            Some(Token::Sum) => Aggregate::Sum,
+            Some(Token::Avg) => Aggregate::Avg,
+            Some(Token::Min) => Aggregate::Min,
+            Some(Token::Max) => Aggregate::Max,
+            _ => return Err(self.error("COUNT, SUM, AVG, MIN or MAX")),
+        };
+        self.bump();
+        Ok(aggregate(self.literal()?))
+    }
+
+    /// `"field" [ASC | DESC]`
+    fn sort_key(&mut self) -> Result<SortKey, RequestError> {
+        let field = self.literal()?;
+        let descending = match self.peek() {
+            Some(Token::Desc) => true,
+            Some(Token::Asc) => false,
+            _ => return Ok(SortKey { field, descending: false }),
+        };
+        self.bump();
+        Ok(SortKey { field, descending })
+    }
+
+    /// Conditions joined by `OR`, which binds weaker than `AND`.
+    fn condition(&mut self) -> Result<Condition, RequestError> {
+        let mut conditions = vec![self.conjunction()?];
+        while self.accept(Token::Or) {
+            conditions.push(self.conjunction()?);
+        }
+        match conditions.len() {
+            1 => Ok(conditions.remove(0)),
+            _ => Ok(Condition::Or(conditions)),
+        }
+    }
+
+    fn conjunction(&mut self) -> Result<Condition, RequestError> {
+        let mut conditions = vec![self.negation()?];
+        while self.accept(Token::And) {
+            conditions.push(self.negation()?);
+        }
+        match conditions.len() {
+            1 => Ok(conditions.remove(0)),
+            _ => Ok(Condition::And(conditions)),
+        }
+    }
+
+    /// `NOT <condition>`, `(<condition>)` or a predicate on a field.
+    ///
+    /// Both nest at most [`MAX_NESTING`] levels deep, so deeply nested conditions can't overflow the stack
+    /// while they are parsed, checked, tested or dropped.
+    fn negation(&mut self) -> Result<Condition, RequestError> {
+        if !matches!(self.peek(), Some(Token::Not) | Some(Token::OpenParen)) {
+            return self.predicate();
+        }
+        if self.nesting == MAX_NESTING {
+            return Err(self.error(format!("a field, NOT and ( nest at most {} levels deep", MAX_NESTING)));
+        }
+        self.nesting += 1;
+        let condition = match self.bump().token {
+            Token::Not => self.negation().map(|condition| Condition::Not(Box::new(condition))),
+            _ => self.condition().and_then(|condition| {
+                self.expect(Token::CloseParen)?;
+                Ok(condition)
+            }),
+        };
+        self.nesting -= 1;
+        condition
+    }
+
+    /// `"field" IS [NOT] NULL`, `"field" <operator> <value>`, `"field" [NOT] LIKE "pattern"` or `"field" [NOT] IN (<value>, ...)`
+    fn predicate(&mut self) -> Result<Condition, RequestError> {
+        if self.peek() != Some(Token::Literal) {
+            return Err(self.error("a field, NOT or ("));
+        }
+        let field = self.literal()?;
+        if self.accept(Token::Is) {
+            let negated = self.accept(Token::Not);
+            self.expect(Token::Null)?;
+            return Ok(Condition::IsNull { field, negated });
+        }
+        let operator = match self.peek() {
+            Some(Token::Equal) => Some(Operator::Equal),
+            Some(Token::NotEqual) => Some(Operator::NotEqual),
+            Some(Token::Less) => Some(Operator::Less),
+            Some(Token::LessEqual) => Some(Operator::LessEqual),
+            Some(Token::Greater) => Some(Operator::Greater),
+            Some(Token::GreaterEqual) => Some(Operator::GreaterEqual),
+            _ => None,
+        };
+        if let Some(operator) = operator {
+            self.bump();
+            let value = self.expr()?;
+            return Ok(Condition::Compare { field, operator, value });
+        }
+
+        let negated = self.accept(Token::Not);
+        let condition = match self.peek() {
+            Some(Token::Like) => {
+                self.bump();
+                Condition::Like { field, pattern: self.literal()? }
+            },
+            Some(Token::In) => {
+                self.bump();
+                self.expect(Token::OpenParen)?;
+                let mut values = vec![self.expr()?];
+                while self.accept(Token::Comma) {
+                    values.push(self.expr()?);
+                }
+                self.expect(Token::CloseParen)?;
+                Condition::In { field, values }
+            },
+            _ if negated => return Err(self.error("LIKE or IN")),
+            _ => return Err(self.error("IS, a comparison, NOT, LIKE or IN")),
+        };
+        match negated {
+            true => Ok(Condition::Not(Box::new(condition))),
+            false => Ok(condition),
+        }
+    }
+
+    fn action(&mut self, expected: &str) -> Result<Action, RequestError> {
+        match self.peek() {
+            Some(Token::Get) => {
+                self.bump();
+                self.fields().map(Action::Get)
+            },
+            Some(Token::Set) => {
+                self.bump();
+                let field = self.literal()?;
+                self.expect(Token::Value)?;
+                let value = self.expr()?;
+                Ok(Action::Set { field, value })
+            },
+            Some(Token::Append) => {
+                self.bump();
+                let field = self.literal()?;
+                self.expect(Token::Value)?;
+                let value = self.expr()?;
+                Ok(Action::Append { field, value })
+            },
+            Some(Token::Remove) => {
+                self.bump();
+                let field = self.literal()?;
+                self.expect(Token::Value)?;
+                let value = self.expr()?;
+                Ok(Action::Remove { field, value })
+            },
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    /// One or more field names, like the ones after `GET`.
+    fn fields(&mut self) -> Result<Vec<String>, RequestError> {
+        let mut fields = vec![self.literal()?];
+        while self.peek() == Some(Token::Literal) {
+            fields.push(self.literal()?);
+        }
+        Ok(fields)
+    }
+
+    fn expr(&mut self) -> Result<Expr, RequestError> {
+        match self.peek() {
+            Some(Token::Literal) => Ok(Expr::String(self.bump().value)),
+            Some(Token::Integer) => self.integer().map(Expr::Integer),
+            Some(Token::Float) => self.float().map(Expr::Float),
+            Some(Token::True) | Some(Token::False) => Ok(Expr::Boolean(self.bump().token == Token::True)),
+            Some(Token::Null) => {
+                self.bump();
+                Ok(Expr::Null)
+            },
+            Some(Token::TimestampType) => {
+                self.bump();
+                self.typed_literal(|value| parse_timestamp(value).ok().map(Expr::Timestamp))
+            },
+            Some(Token::BytesType) => {
+                self.bump();
+                self.typed_literal(|value| parse_bytes(value).ok().map(Expr::Bytes))
+            },
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    /// Parses the string literal behind a type keyword, like the one in `TIMESTAMP "2022-05-02T08:00:00Z"`.
+    fn typed_literal(&mut self, parse: impl Fn(&str) -> Option<Expr>) -> Result<Expr, RequestError> {
+        if self.peek() != Some(Token::Literal) {
+            return Err(self.error(Token::Literal));
+        }
+        match parse(&self.tokens[self.position].value) {
+            Some(expr) => {
+                self.bump();
+                Ok(expr)
+            },
+            None => Err(RequestError::InvalidLiteral(self.tokens[self.position].to_string())),
+        }
+    }
+
+    /// Parses a value that fits into a field of the given [`DataType`].
+    fn value_of(&mut self, data_type: &DataType) -> Result<Expr, RequestError> {
+        match (data_type, self.peek()) {
+            (_, Some(Token::Null)) => self.expr(),
+            (DataType::STRING, Some(Token::Literal)) => Ok(Expr::String(self.bump().value)),
+            (DataType::INTEGER, Some(Token::Integer)) => self.integer().map(Expr::Integer),
+            (DataType::FLOAT, Some(Token::Integer)) => self.integer().map(|value| Expr::Float(value as f64)),
+            (DataType::FLOAT, Some(Token::Float)) => self.float().map(Expr::Float),
+            (DataType::BOOLEAN, Some(Token::True) | Some(Token::False)) => self.expr(),
+            (DataType::TIMESTAMP, Some(Token::TimestampType)) => self.expr(),
+            (DataType::BYTES, Some(Token::BytesType)) => self.expr(),
+            (DataType::STRING, _) => Err(self.error(Token::Literal)),
+            (DataType::INTEGER, _) => Err(self.error(Token::Integer)),
+            (DataType::FLOAT, _) => Err(self.error(Token::Float)),
+            (DataType::BOOLEAN, _) => Err(self.error("TRUE or FALSE")),
+            (DataType::TIMESTAMP, _) => Err(self.error(Token::TimestampType)),
+            (DataType::BYTES, _) => Err(self.error(Token::BytesType)),
+            (DataType::TEMPLATE(_) | DataType::LIST(_), _) => Err(self.error(Token::ENDL)),
+        }
+    }
+
+    fn integer(&mut self) -> Result<i64, RequestError> {
+        match self.tokens[self.position].value.parse::<i64>() {
+            Ok(value) => {
+                self.bump();
+                Ok(value)
+            },
+            Err(_) => Err(RequestError::InvalidLiteral(self.tokens[self.position].to_string())),
+        }
+    }
+
+    /// Literals too large for a float would be infinite, so they are rejected like integers out of range.
+    fn float(&mut self) -> Result<f64, RequestError> {
+        match self.tokens[self.position].value.parse::<f64>() {
+            Ok(value) if value.is_finite() => {
+                self.bump();
+                Ok(value)
+            },
+            _ => Err(RequestError::InvalidLiteral(self.tokens[self.position].to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer;
+
+    use super::*;
+
+    fn parse_source(source: &str) -> Result<Vec<Statement>, RequestError> {
+        parse(lexer::parse(source.to_string()))
+    }
+
+    fn syntax_error(source: &str) -> SyntaxError {
+        match parse_source(source) {
+            Err(RequestError::SyntaxError(err)) => err,
+            result => panic!("expected a syntax error for {:?}, got {:?}", source, result),
+        }
+    }
+
+    #[test]
+    fn parses_template_declaration() {
+        let statements = parse_source("TYPE \"DAY\";\nNAME \"First\" TYPE STRING STARTING \"Math\";\nNAME \"Day\" TYPE INTEGER MIN 1 MAX 7;\nEND;").unwrap();
+        assert_eq!(statements, vec![Statement::DeclareTemplate(TemplateDecl {
+            name: "DAY".to_string(),
+            parent: None,
+            fields: vec![
+                FieldDecl {
+                    name: "First".to_string(),
+                    data_type: DataType::STRING,
+                    starting: Some(Expr::String("Math".to_string())),
+                    constraints: vec![],
+                },
+                FieldDecl {
+                    name: "Day".to_string(),
+                    data_type: DataType::INTEGER,
+                    starting: None,
+                    constraints: vec![Constraint::Min(1.0), Constraint::Max(7.0)],
+                },
+            ],
+        })]);
+    }
+
+    #[test]
+    fn parses_query_with_actions() {
+        let statements = parse_source("QUERY \"Monday\" THEN;\nSET \"First\" VALUE \"Science\";\nGET \"First\" \"Day\";\nEND;").unwrap();
+        assert_eq!(statements, vec![Statement::Query {
+            instance: "Monday".to_string(),
+            condition: None,
+            actions: vec![
+                Action::Set { field: "First".to_string(), value: Expr::String("Science".to_string()) },
+                Action::Get(vec!["First".to_string(), "Day".to_string()]),
+            ],
+        }]);
+    }
+
+    #[test]
+    fn parses_several_statements_and_skips_empty_ones() {
+        let statements = parse_source("CREATE \"Monday\" TYPE \"DAY\";;\nDELETE \"Monday\";\nDELETE TYPE \"DAY\"").unwrap();
+        assert_eq!(statements, vec![
+            Statement::Create { instance: "Monday".to_string(), template: "DAY".to_string(), actions: vec![] },
+            Statement::DeleteInstance("Monday".to_string()),
+            Statement::DeleteTemplate("DAY".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn parses_type_query() {
+        let statements = parse_source("QUERY TYPE \"DAY\" WHERE \"Day\" >= 3 ORDER BY \"Day\" DESC, \"First\" LIMIT 10 OFFSET 20 GET \"First\";").unwrap();
+        assert_eq!(statements, vec![Statement::QueryType(TypeQuery {
+            template: "DAY".to_string(),
+            derived: false,
+            condition: Some(Condition::Compare {
+                field: "Day".to_string(),
+                operator: Operator::GreaterEqual,
+                value: Expr::Integer(3),
+            }),
+            order: vec![
+                SortKey { field: "Day".to_string(), descending: true },
+                SortKey { field: "First".to_string(), descending: false },
+            ],
+            limit: Some(10),
+            offset: 20,
+            cursor: None,
+            fields: vec!["First".to_string()],
+        })]);
+    }
+
+    #[test]
+    fn reports_position_and_expected_token() {
+        let err = syntax_error("QUERY \"Monday\" THEN;\nSET \"First\" VALUE \"Science\";\nSET \"Second\" \"CS\";\nEND;");
+        assert_eq!(err, SyntaxError {
+            token: Some("\"CS\"".to_string()),
+            after: Some("SET \"Second\"".to_string()),
+            expected: "VALUE".to_string(),
+            line: 3,
+            column: 14,
+        });
+        assert_eq!(err.to_string(), "expected VALUE after SET \"Second\" at 3:14, found \"CS\"");
+    }
+
+    #[test]
+    fn reports_end_of_source() {
+        let err = syntax_error("CREATE \"Monday\" TYPE");
+        assert_eq!(err.token, None);
+        assert_eq!(err.expected, "string literal");
+        assert_eq!((err.line, err.column), (1, 21));
+    }
+
+    #[test]
+    fn reports_unknown_statement() {
+        let err = syntax_error("CREATE \"Monday\" TYPE \"DAY\";\nGET \"First\";");
+        assert_eq!(err.token, Some("GET".to_string()));
+        assert_eq!(err.after, None);
+        assert_eq!(err.expected, "TYPE, CREATE, QUERY, ALTER, DELETE, GRANT or REVOKE");
+        assert_eq!((err.line, err.column), (2, 1));
+    }
+
+    #[test]
+    fn rejects_literals_out_of_range() {
+        assert!(matches!(
+            parse_source("QUERY TYPE \"DAY\" LIMIT 99999999999999999999;"),
+            Err(RequestError::InvalidLiteral(literal)) if literal == "99999999999999999999",
+        ));
+        assert!(matches!(
+            parse_source("QUERY TYPE \"DAY\" LIMIT -1;"),
+            Err(RequestError::InvalidLiteral(literal)) if literal == "-1",
+        ));
+        assert!(matches!(
+            parse_source("QUERY \"Monday\" THEN; SET \"Day\" VALUE -9223372036854775809; END;"),
+            Err(RequestError::InvalidLiteral(literal)) if literal == "-9223372036854775809",
+        ));
+        let huge = format!("1{}.0", "0".repeat(400));
+        assert!(matches!(
+            parse_source(&format!("QUERY TYPE \"DAY\" WHERE \"Seconds\" > {};", huge)),
+            Err(RequestError::InvalidLiteral(literal)) if literal == huge,
+        ));
+    }
+
+    #[test]
+    fn limits_nesting_of_conditions() {
+        let nested = |prefix: &str, depth: usize, suffix: &str| format!(
+            "QUERY TYPE \"DAY\" WHERE {}\"Day\" = 1{};", prefix.repeat(depth), suffix.repeat(depth),
+        );
+        assert!(parse_source(&nested("NOT ", MAX_NESTING, "")).is_ok());
+        assert!(parse_source(&nested("(", MAX_NESTING, ")")).is_ok());
+
+        let err = syntax_error(&nested("NOT ", 200_000, ""));
+        assert_eq!(err.token, Some("NOT".to_string()));
+        assert_eq!(err.expected, "a field, NOT and ( nest at most 64 levels deep");
+        let err = syntax_error(&nested("(", 200_000, ")"));
+        assert_eq!(err.token, Some("(".to_string()));
+    }
+
+    #[test]
+    fn keeps_long_chains_of_conditions_flat() {
+        let chain = vec!["\"Day\" = 1"; 20_000].join(" AND ");
+        let statements = parse_source(&format!("QUERY TYPE \"DAY\" WHERE {} OR \"Day\" = 2;", chain)).unwrap();
+        let condition = match &statements[..] {
+            [Statement::QueryType(query)] => query.condition.clone(),
+            _ => None,
+        };
+        match condition {
+            Some(Condition::Or(conditions)) => match &conditions[..] {
+                [Condition::And(chain), Condition::Compare { .. }] => assert_eq!(chain.len(), 20_000),
+                conditions => panic!("expected an AND chain and a comparison, got {} conditions", conditions.len()),
+            },
+            condition => panic!("expected OR, got {:?}", condition),
+        }
+    }
+
+    #[test]
+    fn rejects_empty_pages() {
+        assert!(matches!(
+            parse_source("QUERY TYPE \"DAY\" CURSOR 0;"),
+            Err(RequestError::InvalidLiteral(literal)) if literal == "0",
+        ));
+        assert!(parse_source("QUERY TYPE \"DAY\" CURSOR 1;").is_ok());
+    }
+
+    #[test]
+    fn parses_negative_numbers() {
+        let statements = parse_source("TYPE \"DELTA\"; NAME \"Change\" TYPE FLOAT STARTING -0.5 MIN -10 MAX 10; END;").unwrap();
+        assert_eq!(statements, vec![Statement::DeclareTemplate(TemplateDecl {
+            name: "DELTA".to_string(),
+            parent: None,
+            fields: vec![FieldDecl {
+                name: "Change".to_string(),
+                data_type: DataType::FLOAT,
+                starting: Some(Expr::Float(-0.5)),
+                constraints: vec![Constraint::Min(-10.0), Constraint::Max(10.0)],
+            }],
+        })]);
+
+        let statements = parse_source("QUERY \"Monday\" WHERE \"Day\" < -5 THEN; SET \"Day\" VALUE -9223372036854775808; END;").unwrap();
+        assert_eq!(statements, vec![Statement::Query {
+            instance: "Monday".to_string(),
+            condition: Some(Condition::Compare {
+                field: "Day".to_string(),
+                operator: Operator::Less,
+                value: Expr::Integer(-5),
+            }),
+            actions: vec![Action::Set { field: "Day".to_string(), value: Expr::Integer(i64::MIN) }],
+        }]);
+    }
+
+    #[test]
+    fn parses_boolean_timestamp_and_bytes_literals() {
+        let statements = parse_source(concat!(
+            "TYPE \"SHIFT\"; ",
+            "NAME \"Night\" TYPE BOOLEAN STARTING TRUE; ",
+            "NAME \"Start\" TYPE TIMESTAMP STARTING TIMESTAMP \"2022-05-02T22:00:00+02:00\"; ",
+            "NAME \"Chip\" TYPE BYTES STARTING BYTES \"AP8=\"; ",
+            "END;",
+        )).unwrap();
+        let starting: Vec<_> = match &statements[..] {
+            [Statement::DeclareTemplate(decl)] => decl.fields.iter().map(|field| field.starting.clone()).collect(),
+            statements => panic!("expected a template declaration, got {:?}", statements),
+        };
+        assert_eq!(starting, vec![
+            Some(Expr::Boolean(true)),
+            Some(Expr::Timestamp(time::macros::datetime!(2022-05-02 20:00 UTC))),
+            Some(Expr::Bytes(vec![0, 255])),
+        ]);
+
+        let statements = parse_source("QUERY \"Monday\" WHERE \"Night\" = FALSE THEN; END;").unwrap();
+        assert!(matches!(
+            &statements[..],
+            [Statement::Query { condition: Some(Condition::Compare { value: Expr::Boolean(false), .. }), .. }],
+        ));
+
+        assert!(matches!(
+            parse_source("QUERY TYPE \"SHIFT\" WHERE \"Start\" < TIMESTAMP \"yesterday\";"),
+            Err(RequestError::InvalidLiteral(literal)) if literal == "\"yesterday\"",
+        ));
+        assert!(matches!(
+            parse_source("QUERY TYPE \"SHIFT\" WHERE \"Chip\" = BYTES \"not base64!\";"),
+            Err(RequestError::InvalidLiteral(_)),
+        ));
+        assert_eq!(syntax_error("TYPE \"SHIFT\"; NAME \"Night\" TYPE BOOLEAN STARTING 1; END;").expected, "TRUE or FALSE");
+    }
+
+    #[test]
+    fn rejects_paths_as_field_names() {
+        for source in [
+            "TYPE \"MEAL\"; NAME \"Lunch.Calories\" TYPE INTEGER; END;",
+            "ALTER TYPE \"MEAL\" ADD NAME \"Tags[0]\" TYPE STRING;",
+            "ALTER TYPE \"MEAL\" RENAME NAME \"Calories\" TO \"Lunch.Calories\";",
+        ] {
+            assert!(matches!(parse_source(source), Err(RequestError::InvalidLiteral(_))), "{}", source);
+        }
+        assert!(parse_source("ALTER TYPE \"MEAL\" DROP NAME \"Lunch.Calories\";").is_ok());
+    }
+}
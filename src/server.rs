@@ -1,104 +1,273 @@
-use std::future::Future;
+use std::{future::Future, net::SocketAddr, num::NonZeroUsize, sync::Arc, time::Duration};
 
-use aes::{Aes256, cipher::{KeyInit, generic_array::GenericArray}, Aes128};
 use serde::Serialize;
-use tokio::{net::{TcpListener, TcpStream}, io::{Interest, AsyncWriteExt}};
+use tokio::{net::{TcpListener, TcpStream}, io::AsyncWriteExt, sync::Semaphore, time::timeout};
 
-use crate::{lexer, query::{self, error::RequestError}, perr, plog, backup::SaveWorker, crypto::Key, pwarn};
+use crate::{lexer, query::{self, Context, error::{RequestError, SyntaxError}}, perr, plog, backup::SaveWorker, crypto::{Kdf, Key, Keyring}, frame::{Frame, MAX_HANDSHAKE_SIZE, MAX_PAYLOAD_SIZE}, auth::Users, pwarn};
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
-static BUFFER_SIZE: usize = 1048576;
 
 #[derive(Serialize)]
 struct ErrorResponse {
-    code: u8,
+    code: u16,
     description: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    /// The field and rule of a constraint violation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule: Option<String>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    syntax: Option<SyntaxError>,
+}
+
+impl ErrorResponse {
+    fn new(code: u16, description: &'static str) -> Self {
+        Self { code, description, message: None, field: None, rule: None, syntax: None }
+    }
 }
 
 impl From<RequestError> for ErrorResponse {
     fn from(err: RequestError) -> Self {
         match err {
-            RequestError::TemplateNonExistent => ErrorResponse {
-                code: 100,
-                description: "You have tried to create an instance of a template that doesn't currently exist.",
+            RequestError::TemplateNonExistent => ErrorResponse::new(
+                100,
+                "You have tried to create an instance of a template that doesn't currently exist.",
+            ),
+            RequestError::TemplateAlreadyExists => ErrorResponse::new(
+                101,
+                "You have tried to create a template that already exists.",
+            ),
+            RequestError::TemplateInUse(template) => ErrorResponse {
+                message: Some(format!("Template \"{}\" embeds or extends it.", template)),
+                ..ErrorResponse::new(102, "You have tried to delete a template that is embedded or extended by another template.")
+            },
+            RequestError::InstanceNonExistent => ErrorResponse::new(
+                200,
+                "You have tried to query a instance that doesn't exist.",
+            ),
+            RequestError::InstanceAlreadyExists => ErrorResponse::new(
+                201,
+                "You have tried to create a instance that already exists.",
+            ),
+            RequestError::FieldNonExistent(field) => ErrorResponse {
+                message: Some(format!("Field \"{}\" doesn't exist.", field)),
+                ..ErrorResponse::new(300, "You have tried to access a field that doesn't exist.")
             },
-            RequestError::TemplateAlreadyExists => ErrorResponse {
-                code: 101,
-                description: "You have tried to create a template that already exists.",
+            RequestError::FieldAlreadyExists(field) => ErrorResponse {
+                message: Some(format!("Field \"{}\" already exists.", field)),
+                ..ErrorResponse::new(303, "You have tried to add a field that already exists.")
             },
-            RequestError::InstanceNonExistent => ErrorResponse {
-                code: 200,
-                description: "You have tried to query a instance that doesn't exist.",
+            RequestError::FieldInherited { field, template } => ErrorResponse {
+                message: Some(format!("Field \"{}\" is inherited from template \"{}\", alter it there.", field, template)),
+                ..ErrorResponse::new(304, "You have tried to alter a field that is inherited from another template.")
             },
-            RequestError::InstanceAlreadyExists => ErrorResponse {
-                code: 201,
-                description: "You have tried to create a instance that already exists.",
+            RequestError::TypeMismatch(message) => ErrorResponse {
+                message: Some(message),
+                ..ErrorResponse::new(301, "You have tried to use a value that doesn't fit the type of its field.")
             },
-            RequestError::SyntaxError => ErrorResponse {
-                code: 10,
-                description: "General syntax error in source.",
+            RequestError::ConstraintViolation { field, rule } => ErrorResponse {
+                message: Some(format!("Field \"{}\" violates {}.", field, rule)),
+                field: Some(field),
+                rule: Some(rule),
+                ..ErrorResponse::new(302, "You have tried to use a value that violates a constraint of its field.")
             },
-            RequestError::SerializationError => ErrorResponse {
-                code: 1,
-                description: "Internal db error, failed to serialize to json string.",
+            RequestError::SyntaxError(err) => ErrorResponse {
+                message: Some(err.to_string()),
+                syntax: Some(err),
+                ..ErrorResponse::new(10, "General syntax error in source.")
             },
+            RequestError::InvalidLiteral(literal) => ErrorResponse {
+                message: Some(format!("Literal {} is out of range or malformed.", literal)),
+                ..ErrorResponse::new(11, "A literal in the source can't be represented by its type.")
+            },
+            RequestError::InvalidCursor(cursor) => ErrorResponse {
+                message: Some(format!("Cursor \"{}\" is malformed or was returned by another query.", cursor)),
+                ..ErrorResponse::new(12, "You have tried to continue a query with a cursor that it didn't return.")
+            },
+            RequestError::InvalidEncoding => ErrorResponse::new(
+                13,
+                "The request isn't valid UTF-8.",
+            ),
+            RequestError::DecryptionFailed => ErrorResponse::new(
+                20,
+                "The request failed to authenticate, it was either tampered with or encrypted using another key.",
+            ),
+            RequestError::AuthenticationFailed => ErrorResponse::new(
+                21,
+                "Authentication failed, either the user doesn't exist or the proof is wrong.",
+            ),
+            RequestError::PermissionDenied(message) => ErrorResponse {
+                message: Some(message),
+                ..ErrorResponse::new(22, "None of your roles has been granted the permission needed for this statement.")
+            },
+            RequestError::SerializationError => ErrorResponse::new(
+                1,
+                "Internal db error, failed to serialize to json string.",
+            ),
         }
     }
 }
 
+/// Confirms a successful handshake.
+#[derive(Serialize)]
+struct AuthResponse<'a> {
+    user: &'a str,
+}
+
+/// State shared between the tasks of every connection.
+struct Shared {
+    /// Keys derived at startup, looked up by the key id of each frame.
+    keyring: Keyring,
+    users: Users,
+    /// Sessions are closed after not receiving a request for this long.
+    idle_timeout: Duration,
+}
 
 struct Server {
     listener: TcpListener,
-    aes_key: Vec<u8>,
+    shared: Arc<Shared>,
+    /// Limits the number of connections that are processed at the same time.
+    connections: Arc<Semaphore>,
     save_worker: SaveWorker,
 }
 
-impl Server {
-    // Process incoming request and pass query to db
-    async fn process(&self, stream: &mut TcpStream) -> Result<(), Error> {
-        (stream.readable().await?, stream.writable().await?);
-        
-        let mut buf = Vec::with_capacity(BUFFER_SIZE);
-        let len = stream.try_read_buf(&mut buf)?;
-        let data = self.decrypt(buf.split_at(len).0.to_vec());
-        let source = String::from_utf8(data)?;
-        // Removing trailing padding 0's from decrypted query
-        let source = source.trim_matches(char::from(0)).to_string();
-        let result = query::data(lexer::parse(source));
-        self.send(stream, result).await?;
+impl Shared {
+    // Authenticate the client and pass the queries of its session to db
+    async fn process(&self, stream: &mut TcpStream, addr: SocketAddr) -> Result<(), Error> {
+        let mut last_request_id = None;
+        if let Some((context, session)) = self.authenticate(stream, &mut last_request_id).await? {
+            plog!("{} authenticated as \"{}\".", addr, context.user);
+            let context = Arc::new(context);
+            while let Some(frame) = self.receive(stream, &mut last_request_id, MAX_PAYLOAD_SIZE).await? {
+                let (request_id, key_id) = (frame.request_id, frame.key_id);
+                let result = match frame.open(&session).map(String::from_utf8) {
+                    // Statements wait for the locks of the data, which must not block the tasks of other connections
+                    Ok(Ok(source)) => {
+                        let context = Arc::clone(&context);
+                        tokio::task::spawn_blocking(move || query::data(lexer::parse(source), &context)).await?
+                    },
+                    Ok(Err(_)) => Err(RequestError::InvalidEncoding),
+                    Err(_) => Err(RequestError::DecryptionFailed),
+                };
+                self.send(stream, request_id, key_id, &session, result).await?;
+            }
+        }
 
         stream.shutdown().await?;
         Ok(())
     }
 
+    /// Runs the challenge-response handshake at the start of every connection.
+    ///
+    /// 1. The client sends the name of its user.
+    /// 2. The server answers with a random challenge.
+    /// 3. The client answers with the HMAC of the challenge, keyed with the key derived from its secret.
+    /// 4. The server answers with the name of the authenticated user or with an error and closes the connection.
+    ///
+    /// Returns the context of the user and the key of the session, which encrypts every following frame,
+    /// or `None` if the client didn't authenticate.
+    async fn authenticate(&self, stream: &mut TcpStream, last_request_id: &mut Option<u32>) -> Result<Option<(Context, Key)>, Error> {
+        let hello = match self.receive(stream, last_request_id, MAX_HANDSHAKE_SIZE).await? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let (request_id, key_id) = (hello.request_id, hello.key_id);
+        let key = self.key(key_id)?;
+        let user = match hello.open(key) {
+            Ok(user) => String::from_utf8(user)?,
+            Err(_) => {
+                self.send(stream, request_id, key_id, key, Err(RequestError::DecryptionFailed)).await?;
+                return Ok(None);
+            },
+        };
+
+        // Unknown users get a challenge as well, so they can't be told apart from wrong secrets
+        let challenge = Users::challenge();
+        Frame::seal(request_id, key_id, key, challenge.to_vec()).write(stream).await?;
+
+        let answer = match self.receive(stream, last_request_id, MAX_HANDSHAKE_SIZE).await? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let (request_id, key_id) = (answer.request_id, answer.key_id);
+        let key = self.key(key_id)?;
+        let proof = answer.open(key).unwrap_or_default();
+        let session = match self.users.session_key(&user, &challenge) {
+            Some(session) if self.users.verify(&user, &challenge, &proof) => session,
+            _ => {
+                self.send(stream, request_id, key_id, key, Err(RequestError::AuthenticationFailed)).await?;
+                return Ok(None);
+            },
+        };
+        let response = serde_json::to_string(&AuthResponse { user: &user })?;
+        self.send(stream, request_id, key_id, key, Ok(response)).await?;
+        let roles = self.users.roles(&user);
+        Ok(Some((Context { user, roles }, session)))
+    }
+
+    /// Receives the next frame, returns `None` once the client closed the connection or was idle for too long.
+    ///
+    /// Request ids have to increase within a connection, so a recorded frame can't be replayed.
+    /// Frames with an id that doesn't increase or a payload bigger than `max_size` end the connection.
+    async fn receive(&self, stream: &mut TcpStream, last_request_id: &mut Option<u32>, max_size: usize) -> Result<Option<Frame>, Error> {
+        let frame = match timeout(self.idle_timeout, Frame::read(stream, max_size)).await {
+            Ok(frame) => frame?,
+            Err(_) => None,
+        };
+        if let Some(frame) = &frame {
+            match *last_request_id {
+                Some(last) if frame.request_id <= last => {
+                    return Err(format!("Request id {} doesn't increase after {}", frame.request_id, last).into());
+                },
+                _ => *last_request_id = Some(frame.request_id),
+            }
+        }
+        Ok(frame)
+    }
+
+    /// Without the key there is no way to send an encrypted response, so unknown key ids end the connection.
+    fn key(&self, key_id: u8) -> Result<&Key, Error> {
+        Ok(self.keyring.get(key_id).ok_or_else(|| format!("Frame uses unknown key id {}", key_id))?)
+    }
+
     /// Send serialized data as json-string or send error message.
-    async fn send(&self, stream: &mut TcpStream, data: Result<String, RequestError>) -> Result<(), Error> {
+    ///
+    /// The response is encrypted with the same key as the request.
+    async fn send(&self, stream: &mut TcpStream, request_id: u32, key_id: u8, key: &Key, data: Result<String, RequestError>) -> Result<(), Error> {
         let output = match data {
             Ok(value) => value.into_bytes(),
             Err(error) => { 
-                let response = serde_json::to_string(&ErrorResponse::from(error)).unwrap();
+                let response = serde_json::to_string(&ErrorResponse::from(error))?;
                 response.into_bytes()
             },
         };
-        stream.write(&self.encrypt(output)).await?;
+        Frame::seal(request_id, key_id, key, output).write(stream).await?;
         Ok(())
     }
+}
 
-    fn encrypt(&self, input: Vec<u8>) -> Vec<u8> {
-        let key: Key = self.aes_key.clone().into();
-        key.encrypt(input)
-    }
-
-    fn decrypt(&self, input: Vec<u8>) -> Vec<u8> {
-        let key: Key = self.aes_key.clone().into();
-        key.decrypt(input)
-    }
-
-    /// Process incoming requests in a loop.
+impl Server {
+    /// Accept incoming connections in a loop and process each of them on its own task.
     async fn run(&mut self) -> Result<(), Error> {
         loop {
-            let (mut stream, addr) = self.listener.accept().await?;
-            self.process(&mut stream).await?;
+            // Wait for a free slot before accepting the next connection
+            let permit = Arc::clone(&self.connections).acquire_owned().await?;
+            let (mut stream, addr) = match self.listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    pwarn!("Failed to accept connection: {}", err);
+                    continue;
+                },
+            };
+            let shared = Arc::clone(&self.shared);
+            tokio::spawn(async move {
+                if let Err(err) = shared.process(&mut stream, addr).await {
+                    pwarn!("Failed to process request from {}: {}", addr, err);
+                }
+                drop(permit);
+            });
         }
     }
 }
@@ -117,17 +286,39 @@ pub async fn run(listener: TcpListener, shutdown: impl Future)  {
 
     let save_worker = SaveWorker::new();
 
-    let aes_key = match std::env::var("AES_KEY") {
-        Ok(key) => key.into_bytes(),
-        Err(_) => return perr!("Failed to read AES-Key, needed for encryption!"),
+    let kdf = match Kdf::from_env() {
+        Ok(kdf) => kdf,
+        Err(err) => return perr!("Invalid key derivation parameters: {}", err),
+    };
+    let keyring = match Keyring::from_env(&kdf) {
+        Ok(keyring) => keyring,
+        Err(err) => return perr!("Failed to read AES-Keys, needed for encryption: {}", err),
+    };
+    plog!("Derived keys with ids {:?}.", keyring.ids());
+    let users = match Users::from_env(&kdf) {
+        Ok(users) => users,
+        Err(err) => return perr!("Failed to read users, needed for authentication: {}", err),
+    };
+    plog!("Loaded users {:?}.", users.names());
+
+    let max_connections = match positive_var("MAX_CONNECTIONS", 256) {
+        Ok(max_connections) => max_connections,
+        Err(err) => return perr!("Invalid connection limit: {}", err),
+    };
+    let idle_timeout = match positive_var("IDLE_TIMEOUT", 60) {
+        Ok(idle_timeout) => idle_timeout as u64,
+        Err(err) => return perr!("Invalid idle timeout: {}", err),
     };
 
     let mut server = Server {
         listener,
-        aes_key,
+        shared: Arc::new(Shared { keyring, users, idle_timeout: Duration::from_secs(idle_timeout) }),
+        connections: Arc::new(Semaphore::new(max_connections)),
         save_worker
     };
 
+    plog!("Accepting up to {} connections at the same time.", max_connections);
+
     tokio::select! {
         res = server.run() => {
             if let Err(err) = res {
@@ -136,4 +327,90 @@ pub async fn run(listener: TcpListener, shutdown: impl Future)  {
         }
         _ = shutdown => {}
     }
-}
\ No newline at end of file
+}
+
+/// Reads a positive number from the environment variable, unset variables fall back to the default.
+///
+/// Zero isn't allowed, since no connection would ever be accepted or stay open.
+fn positive_var(name: &str, default: usize) -> Result<usize, String> {
+    match std::env::var(name) {
+        Ok(value) => value.parse::<NonZeroUsize>()
+            .map(NonZeroUsize::get)
+            .map_err(|_| format!("{} has to be a positive number, not \"{}\"", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    use super::*;
+
+    /// Runs the handshake of a client with the secret and returns the response and the key of the session.
+    async fn handshake(stream: &mut TcpStream, transport: &Key, kdf: &Kdf, secret: &str) -> (String, Key) {
+        Frame::seal(1, 0, transport, b"alice".to_vec()).write(stream).await.unwrap();
+        let challenge = Frame::read(stream, MAX_HANDSHAKE_SIZE).await.unwrap().unwrap().open(transport).unwrap();
+
+        let mut user_key = [0u8; 32];
+        kdf.derive(secret.as_bytes(), b"alice", &mut user_key);
+        let mut mac = Hmac::<Sha256>::new_from_slice(&user_key).unwrap();
+        mac.update(&challenge);
+        Frame::seal(2, 0, transport, mac.finalize().into_bytes().to_vec()).write(stream).await.unwrap();
+        let response = Frame::read(stream, MAX_PAYLOAD_SIZE).await.unwrap().unwrap().open(transport).unwrap();
+        (String::from_utf8(response).unwrap(), Key::session(&user_key, &challenge))
+    }
+
+    #[tokio::test]
+    async fn authenticates_sessions() {
+        let kdf = Kdf::new("test".to_string(), 1);
+        // No other test reads USERS_FILE
+        let path = std::env::temp_dir().join(format!("perdia-handshake-{}.json", std::process::id()));
+        std::fs::write(&path, r#"[{"name": "alice", "secret": "wonderland"}]"#).unwrap();
+        std::env::set_var("USERS_FILE", &path);
+        let users = Users::from_env(&kdf).unwrap();
+        std::env::remove_var("USERS_FILE");
+        std::fs::remove_file(&path).unwrap();
+
+        let shared = Arc::new(Shared {
+            keyring: Keyring::parse(Some("transport".to_string()), "", &kdf).unwrap(),
+            users,
+            idle_timeout: Duration::from_secs(5),
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, addr) = listener.accept().await.unwrap();
+                let shared = Arc::clone(&shared);
+                tokio::spawn(async move { shared.process(&mut stream, addr).await });
+            }
+        });
+        let transport = Key::derive(b"transport", &kdf);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let (response, session) = handshake(&mut stream, &transport, &kdf, "wonderland").await;
+        assert_eq!(response, r#"{"user":"alice"}"#);
+        Frame::seal(3, 0, &session, b"QUERY TYPE;".to_vec()).write(&mut stream).await.unwrap();
+        let frame = Frame::read(&mut stream, MAX_PAYLOAD_SIZE).await.unwrap().unwrap();
+        assert_eq!(frame.request_id, 3);
+        assert!(frame.open(&session).is_ok());
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let (response, _) = handshake(&mut stream, &transport, &kdf, "looking glass").await;
+        assert!(response.contains("\"code\":21"), "{}", response);
+        assert_eq!(Frame::read(&mut stream, MAX_PAYLOAD_SIZE).await.unwrap(), None);
+    }
+
+    #[test]
+    fn reads_positive_numbers_from_the_environment() {
+        assert_eq!(positive_var("PERDIA_TEST_UNSET", 256), Ok(256));
+        std::env::set_var("PERDIA_TEST_POSITIVE", "8");
+        assert_eq!(positive_var("PERDIA_TEST_POSITIVE", 256), Ok(8));
+        for value in ["0", "-1", "many", ""] {
+            std::env::set_var("PERDIA_TEST_INVALID", value);
+            assert!(positive_var("PERDIA_TEST_INVALID", 256).is_err(), "{:?}", value);
+        }
+    }
+}
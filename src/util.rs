@@ -1,6 +1,6 @@
 use time::{macros::format_description, format_description::FormatItem};
 
-pub const DATE_FORMAT_STR: &'static [FormatItem<'static>] = format_description!("[day]-[month]-[year] - [hour]:[minute]:[second]Z");
+pub const DATE_FORMAT_STR: &[FormatItem<'static>] = format_description!("[day]-[month]-[year] - [hour]:[minute]:[second]Z");
 
 pub fn prefix() -> String {
     use time::OffsetDateTime; 
@@ -14,7 +14,7 @@ pub fn prefix() -> String {
 macro_rules! plog {
     () => (print!("\n"));
     ($($arg:tt)*) => ({ 
-        use crate::util::prefix;
+        use $crate::util::prefix;
         use colored::*;
         print!("{}{}\n", 
             prefix(),
@@ -27,7 +27,7 @@ macro_rules! plog {
 macro_rules! pwarn {
     () => (print!("\n"));
     ($($arg:tt)*) => ({ 
-        use crate::util::prefix;
+        use $crate::util::prefix;
         use colored::*;
         print!("{}{}\n", 
             prefix(),
@@ -40,7 +40,7 @@ macro_rules! pwarn {
 macro_rules! perr {
     () => (print!("\n"));
     ($($arg:tt)*) => ({ 
-        use crate::util::prefix;
+        use $crate::util::prefix;
         use colored::*;
         print!("{}{}\n", 
             prefix(),